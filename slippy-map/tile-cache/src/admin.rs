@@ -0,0 +1,84 @@
+//! Authentication of the admin routes, which make the server do expensive work.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+
+use crate::{config::AdminConfig, AppState};
+
+pub async fn require_admin<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let admin = &state.config.admin;
+    if admin.token.is_none() && admin.username.is_none() {
+        return (
+            StatusCode::FORBIDDEN,
+            "Admin routes are disabled, set `admin.token` or `admin.username` and `admin.password` in the config",
+        )
+            .into_response();
+    }
+
+    if is_authorized(admin, req.headers()) {
+        return next.run(req).await;
+    }
+
+    tracing::warn!("Unauthorized request to {}", req.uri().path());
+    let challenge = if admin.username.is_some() {
+        r#"Basic realm="tile-cache admin""#
+    } else {
+        "Bearer"
+    };
+    let mut resp = (StatusCode::UNAUTHORIZED, "Admin credentials required").into_response();
+    resp.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static(challenge),
+    );
+    resp
+}
+
+fn is_authorized(admin: &AdminConfig, headers: &HeaderMap) -> bool {
+    let Some(authorization) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let Some((scheme, credentials)) = authorization.split_once(' ') else {
+        return false;
+    };
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return match admin.token {
+            Some(ref token) => constant_time_eq(credentials.as_bytes(), token.as_bytes()),
+            None => false,
+        };
+    }
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        let (Some(username), Some(password)) = (&admin.username, &admin.password) else {
+            return false;
+        };
+        let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(credentials) else {
+            return false;
+        };
+        let expected = format!("{username}:{password}");
+        return constant_time_eq(&decoded, expected.as_bytes());
+    }
+
+    false
+}
+
+/// Compare secrets without revealing how long the matching prefix is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,504 @@
+use std::path::{Path, PathBuf};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::MAX_ZOOM;
+
+/// Where the config file is looked up if `TILE_CACHE_CONFIG` is not set
+const DEFAULT_CONFIG_PATH: &str = "tile-cache.toml";
+
+/// Upstream URLs starting with this name a local MBTiles file instead,
+/// like `mbtiles:tilesets/russia.mbtiles`
+pub const MBTILES_URL_PREFIX: &str = "mbtiles:";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// Address and port to listen on
+    #[serde(default = "default_listen")]
+    pub listen: String,
+
+    /// Base URL that clients use to reach this server, like `https://tiles.example.com`.
+    /// If unset, it is derived from the `Host` header of each request.
+    #[serde(default)]
+    pub public_url: Option<String>,
+
+    /// How downloaded tiles are stored
+    #[serde(default)]
+    pub store: StoreKind,
+
+    /// Directory where downloaded tiles are stored
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: PathBuf,
+
+    /// Keep identical tiles only once, by the hash of their contents
+    #[serde(default)]
+    pub dedup: bool,
+
+    /// Origins allowed to read tiles from scripts, for example to draw them into a canvas.
+    /// `["*"]` allows any origin, an empty list disables CORS headers.
+    #[serde(default = "default_cors_allow_origins")]
+    pub cors_allow_origins: Vec<String>,
+
+    /// Fetch tiles around the requested ones in the background, following each style's
+    /// `precache` policy. Turn off to save on metered upstreams.
+    #[serde(default = "default_true")]
+    pub precache_enabled: bool,
+
+    /// Credentials for the admin routes under `/admin`
+    #[serde(default)]
+    pub admin: AdminConfig,
+
+    /// Limits on how fast each client can request public routes
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// Learning which areas are viewed the most, and refreshing them every night
+    #[serde(default)]
+    pub hot_areas: HotAreasConfig,
+
+    #[serde(default = "default_styles")]
+    pub styles: Vec<StyleConfig>,
+}
+
+/// Admin routes accept either the bearer token, or HTTP basic auth with the username and password.
+/// If neither is set, admin routes are disabled.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub token: Option<String>,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second allowed for each client; 0 disables the limit
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: u32,
+
+    /// Requests a client can make at once after being idle, like when a map is first shown
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+
+    /// Identify clients by the last address in `X-Forwarded-For`, the one the proxy added,
+    /// which is only trustworthy behind a reverse proxy that sets it
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: default_requests_per_second(),
+            burst: default_burst(),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+/// Tile requests are counted by area, which is the tile containing them at `area_zoom`.
+/// Every night the `top_areas` most viewed areas are downloaded again, with `ring` areas around
+/// them and `levels_down` levels below, and the counts are halved to favor recent views.
+#[derive(Clone, Debug, Deserialize)]
+pub struct HotAreasConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    #[serde(default = "default_area_zoom")]
+    pub area_zoom: u8,
+
+    #[serde(default = "default_top_areas")]
+    pub top_areas: usize,
+
+    /// Areas around each hot one to download if missing, in each direction, at most 3
+    #[serde(default = "default_hot_area_ring")]
+    pub ring: u8,
+
+    /// Levels of subtiles below each area, at most 4
+    #[serde(default = "default_levels_down")]
+    pub levels_down: u8,
+
+    /// Time of day in UTC to refresh the hot areas at, like `03:00`
+    #[serde(default = "default_run_at")]
+    pub run_at: String,
+
+    /// File keeping the counts across restarts.
+    /// Defaults to `access-counts.tsv` in `cache_dir`, unless tiles are stored in memory.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+impl Default for HotAreasConfig {
+    fn default() -> Self {
+        HotAreasConfig {
+            enabled: true,
+            area_zoom: default_area_zoom(),
+            top_areas: default_top_areas(),
+            ring: default_hot_area_ring(),
+            levels_down: default_levels_down(),
+            run_at: default_run_at(),
+            file: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// One PNG file per tile in `cache_dir`
+    #[default]
+    Directory,
+    /// One MBTiles file per style in `cache_dir`; needs the `mbtiles` feature
+    Mbtiles,
+    /// Nothing is kept across restarts
+    Memory,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StyleConfig {
+    /// Identifier of the style, used as the first path component of tile URLs.
+    /// It cannot contain `@`, which separates the style from the name of a snapshot.
+    #[serde(deserialize_with = "deserialize_style_name")]
+    pub name: String,
+
+    /// Human-readable name of the style
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// Upstream URL template. `{s}`, `{z}`, `{x}` and `{y}` are replaced
+    /// with the subdomain, zoom and coordinates of the tile, and `{q}` with its quadkey.
+    /// Local XYZ servers work the same, and `mbtiles:<path>` reads tiles from an MBTiles file.
+    pub url: String,
+
+    /// How the upstream addresses tiles. Defaults to `tms` for MBTiles files,
+    /// as in their spec, and to `xyz` otherwise.
+    #[serde(default)]
+    pub scheme: Option<TileScheme>,
+
+    /// Upstream URL template for `@2x` tiles, if the provider has them.
+    /// Without it, `@2x` tiles are upscaled from the regular ones.
+    #[serde(default)]
+    pub retina_url: Option<String>,
+
+    /// Side in pixels of the tiles served at `url`.
+    /// Providers with 512px tiles can serve `@2x` tiles directly,
+    /// and have their tiles downscaled for regular requests.
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+
+    /// Quality from 0 to 100 of tiles converted to WebP
+    #[serde(default = "default_webp_quality")]
+    pub webp_quality: u8,
+
+    /// Quality from 1 to 100 of tiles converted to JPEG
+    #[serde(default = "default_jpeg_quality")]
+    pub jpeg_quality: u8,
+
+    /// Subdomains that `{s}` can take; clients pick one of them per request
+    #[serde(default = "default_subdomains")]
+    pub subdomains: Vec<String>,
+
+    #[serde(default)]
+    pub minzoom: u8,
+
+    /// Deepest zoom served, at most 31
+    #[serde(default = "default_maxzoom", deserialize_with = "deserialize_maxzoom")]
+    pub maxzoom: u8,
+
+    /// Area covered by the style, as `[west, south, east, north]` in degrees
+    #[serde(default = "default_bounds")]
+    pub bounds: [f64; 4],
+
+    /// Which tiles are fetched in the background when one is requested
+    #[serde(default)]
+    pub precache: PrecachePolicy,
+
+    /// HTML attribution that must be shown next to the map
+    #[serde(default)]
+    pub attribution: String,
+}
+
+/// Ways of numbering tiles at a zoom
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileScheme {
+    /// Rows count from the top of the map, as in the tile URLs of this server
+    Xyz,
+    /// Rows count from the bottom of the map
+    Tms,
+    /// Bing-style quadkeys, given to the URL template as `{q}`
+    Quadkey,
+}
+
+/// Tiles around a requested one that are fetched in advance.
+/// `{ ancestors = false, levels_down = 0 }` turns precaching off for the style.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PrecachePolicy {
+    /// All tiles containing the requested one, down to `minzoom`
+    #[serde(default = "default_true")]
+    pub ancestors: bool,
+
+    /// Levels of subtiles below the requested one, at most 4
+    #[serde(default = "default_levels_down")]
+    pub levels_down: u8,
+
+    /// Tiles around the requested one at the same zoom, this many tiles in each direction, at most 3
+    #[serde(default)]
+    pub neighbor_ring: u8,
+}
+
+impl Default for PrecachePolicy {
+    fn default() -> Self {
+        PrecachePolicy {
+            ancestors: true,
+            levels_down: default_levels_down(),
+            neighbor_ring: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file named by `TILE_CACHE_CONFIG`, or `tile-cache.toml`.
+    /// If there is no such file, the built-in defaults are used.
+    pub fn load() -> Self {
+        let path =
+            std::env::var("TILE_CACHE_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match toml::from_str(&text) {
+                Ok(config) => {
+                    tracing::info!("Loaded config from {path}");
+                    config
+                }
+                Err(why) => panic!("Could not parse config file {path}: {why}"),
+            },
+            Err(why) => {
+                tracing::warn!("Could not read config file {path} ({why}), using defaults");
+                Config::default()
+            }
+        }
+    }
+
+    pub fn style(&self, name: &str) -> Option<&StyleConfig> {
+        self.styles.iter().find(|s| s.name == name)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: default_listen(),
+            public_url: None,
+            store: StoreKind::default(),
+            cache_dir: default_cache_dir(),
+            dedup: false,
+            cors_allow_origins: default_cors_allow_origins(),
+            precache_enabled: true,
+            admin: AdminConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            hot_areas: HotAreasConfig::default(),
+            styles: default_styles(),
+        }
+    }
+}
+
+impl StyleConfig {
+    /// Whether tiles at this scale are made by resizing the tile at scale 1,
+    /// rather than fetched from upstream
+    pub fn upscales(&self, scale: u8) -> bool {
+        scale > 1 && self.retina_url.is_none() && self.tile_size < 256 * scale as u32
+    }
+
+    /// Upstream URL template for tiles at this scale
+    pub fn url_template(&self, scale: u8) -> &str {
+        match self.retina_url {
+            Some(ref retina_url) if scale > 1 => retina_url,
+            _ => &self.url,
+        }
+    }
+
+    /// The local MBTiles file that tiles at this scale are read from, if any
+    pub fn mbtiles_path(&self, scale: u8) -> Option<&Path> {
+        self.url_template(scale)
+            .strip_prefix(MBTILES_URL_PREFIX)
+            .map(Path::new)
+    }
+
+    /// Whether tiles at any scale are read from local MBTiles files
+    pub fn reads_mbtiles(&self) -> bool {
+        std::iter::once(&self.url)
+            .chain(&self.retina_url)
+            .any(|url| url.starts_with(MBTILES_URL_PREFIX))
+    }
+
+    /// How the upstream addresses tiles at this scale
+    pub fn upstream_scheme(&self, scale: u8) -> TileScheme {
+        match self.scheme {
+            Some(scheme) => scheme,
+            None if self.mbtiles_path(scale).is_some() => TileScheme::Tms,
+            None => TileScheme::Xyz,
+        }
+    }
+
+    /// `None` for tiles off the map, which have no row in TMS
+    #[cfg(feature = "online")]
+    pub fn tile_url(&self, idx: &str, tile: crate::TileId) -> Option<String> {
+        let y = match self.upstream_scheme(tile.scale) {
+            TileScheme::Tms => tile.tms_y()?,
+            TileScheme::Xyz | TileScheme::Quadkey => tile.y,
+        };
+        let url = self
+            .url_template(tile.scale)
+            .replace("{s}", idx)
+            .replace("{z}", &tile.zoom.to_string())
+            .replace("{x}", &tile.x.to_string())
+            .replace("{y}", &y.to_string())
+            .replace("{q}", &tile.quadkey());
+        Some(url)
+    }
+}
+
+/// Names with `@` would be taken for snapshots of another style
+fn deserialize_style_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if name.contains(crate::snapshot::SEPARATOR) {
+        return Err(D::Error::custom(format!(
+            "style name `{name}` cannot contain `{}`, which marks snapshots",
+            crate::snapshot::SEPARATOR
+        )));
+    }
+    Ok(name)
+}
+
+/// Deeper zooms have more columns and rows than fit into tile coordinates
+fn deserialize_maxzoom<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let maxzoom = u8::deserialize(deserializer)?;
+    if maxzoom > MAX_ZOOM {
+        tracing::warn!("Style maxzoom {maxzoom} is too deep, using {MAX_ZOOM}");
+    }
+    Ok(maxzoom.min(MAX_ZOOM))
+}
+
+fn default_listen() -> String {
+    "0.0.0.0:3000".to_string()
+}
+
+fn default_cache_dir() -> PathBuf {
+    PathBuf::from("tile-cache")
+}
+
+fn default_cors_allow_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_levels_down() -> u8 {
+    2
+}
+
+fn default_requests_per_second() -> u32 {
+    100
+}
+
+fn default_burst() -> u32 {
+    400
+}
+
+fn default_area_zoom() -> u8 {
+    14
+}
+
+fn default_top_areas() -> usize {
+    10
+}
+
+fn default_hot_area_ring() -> u8 {
+    1
+}
+
+fn default_run_at() -> String {
+    "03:00".to_string()
+}
+
+fn default_tile_size() -> u32 {
+    256
+}
+
+fn default_webp_quality() -> u8 {
+    80
+}
+
+fn default_jpeg_quality() -> u8 {
+    85
+}
+
+fn default_subdomains() -> Vec<String> {
+    vec!["a".to_string(), "b".to_string(), "c".to_string()]
+}
+
+fn default_maxzoom() -> u8 {
+    19
+}
+
+fn default_bounds() -> [f64; 4] {
+    [-180.0, -85.051129, 180.0, 85.051129]
+}
+
+fn default_styles() -> Vec<StyleConfig> {
+    vec![
+        StyleConfig {
+            name: "_".to_string(),
+            title: Some("Default".to_string()),
+            url: "https://tile.openstreetmap.org/{z}/{x}/{y}.png".to_string(),
+            scheme: None,
+            retina_url: None,
+            tile_size: default_tile_size(),
+            webp_quality: default_webp_quality(),
+            jpeg_quality: default_jpeg_quality(),
+            subdomains: default_subdomains(),
+            minzoom: 0,
+            maxzoom: default_maxzoom(),
+            bounds: default_bounds(),
+            precache: PrecachePolicy::default(),
+            attribution: r#"&copy; <a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a> contributors"#.to_string(),
+        },
+        StyleConfig {
+            name: "transportdark".to_string(),
+            title: Some("TransportDark".to_string()),
+            url: "https://{s}.tile.thunderforest.com/transport-dark/{z}/{x}/{y}.png?apikey=db5ae1f5778a448ca662554581f283c5".to_string(),
+            scheme: None,
+            retina_url: Some("https://{s}.tile.thunderforest.com/transport-dark/{z}/{x}/{y}@2x.png?apikey=db5ae1f5778a448ca662554581f283c5".to_string()),
+            tile_size: default_tile_size(),
+            webp_quality: default_webp_quality(),
+            jpeg_quality: default_jpeg_quality(),
+            subdomains: default_subdomains(),
+            minzoom: 0,
+            maxzoom: 22,
+            bounds: default_bounds(),
+            precache: PrecachePolicy::default(),
+            attribution: r#"&copy; <a href="http://www.thunderforest.com/">Thunderforest</a>, &copy; <a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a> contributors"#.to_string(),
+        },
+        StyleConfig {
+            name: "matrix".to_string(),
+            title: Some("Matrix".to_string()),
+            url: "https://{s}.tile.jawg.io/jawg-matrix/{z}/{x}/{y}.png?access-token=PyTJUlEU1OPJwCJlW1k0NC8JIt2CALpyuj7uc066O7XbdZCjWEL3WYJIk6dnXtps".to_string(),
+            scheme: None,
+            retina_url: Some("https://{s}.tile.jawg.io/jawg-matrix/{z}/{x}/{y}@2x.png?access-token=PyTJUlEU1OPJwCJlW1k0NC8JIt2CALpyuj7uc066O7XbdZCjWEL3WYJIk6dnXtps".to_string()),
+            tile_size: default_tile_size(),
+            webp_quality: default_webp_quality(),
+            jpeg_quality: default_jpeg_quality(),
+            subdomains: default_subdomains(),
+            minzoom: 0,
+            maxzoom: 22,
+            bounds: default_bounds(),
+            precache: PrecachePolicy::default(),
+            attribution: r#"<a href="http://jawg.io" title="Tiles Courtesy of Jawg Maps" target="_blank">&copy; <b>Jawg</b>Maps</a> &copy; <a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a> contributors"#.to_string(),
+        },
+    ]
+}
@@ -0,0 +1,20 @@
+use axum::http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+
+/// Strong ETag derived from the contents of a tile
+pub fn content_etag(data: &[u8]) -> String {
+    let hash = Sha256::digest(data);
+    let hex: String = hash[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+/// Whether the client already has this version, according to its `If-None-Match` header
+pub fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
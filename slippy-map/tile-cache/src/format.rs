@@ -0,0 +1,118 @@
+//! Image formats that tiles can be served in.
+
+use std::io::Cursor;
+
+use axum::http::{header, HeaderMap};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageResult};
+
+use crate::config::StyleConfig;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TileFormat {
+    /// What upstream tiles are stored as; other formats are converted from it
+    #[default]
+    Png,
+    Webp,
+    Jpeg,
+}
+
+impl TileFormat {
+    /// In order of preference when the client accepts several equally
+    const ALL: [TileFormat; 3] = [TileFormat::Webp, TileFormat::Png, TileFormat::Jpeg];
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "png" => Some(TileFormat::Png),
+            "webp" => Some(TileFormat::Webp),
+            "jpg" | "jpeg" => Some(TileFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TileFormat::Png => "png",
+            TileFormat::Webp => "webp",
+            TileFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.content_type() == content_type)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TileFormat::Png => "image/png",
+            TileFormat::Webp => "image/webp",
+            TileFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// Pick the format that the client prefers according to its `Accept` header.
+    /// Explicitly listed types win over `image/*` and `*/*` with the same weight.
+    /// Clients without the header, or accepting none of the formats, get PNG.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let accepted: Vec<(&str, f32)> = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| {
+                let mut params = item.split(';');
+                let media_type = params.next()?.trim();
+                let q = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((media_type, q))
+            })
+            .collect();
+
+        let weight = |format: TileFormat| {
+            let exact = accepted
+                .iter()
+                .find(|(media_type, _)| media_type.eq_ignore_ascii_case(format.content_type()));
+            if let Some((_, q)) = exact {
+                return (*q, true);
+            }
+            let wildcard = accepted
+                .iter()
+                .find(|(media_type, _)| *media_type == "image/*")
+                .or_else(|| accepted.iter().find(|(media_type, _)| *media_type == "*/*"));
+            (wildcard.map_or(0.0, |(_, q)| *q), false)
+        };
+
+        let mut best = (TileFormat::Png, (0.0, false));
+        for format in TileFormat::ALL {
+            let weight = weight(format);
+            if weight.0 > 0.0 && weight > best.1 {
+                best = (format, weight);
+            }
+        }
+        best.0
+    }
+
+    /// Re-encode a PNG tile into this format, with the style's quality setting
+    pub fn convert(&self, png_data: &[u8], style: &StyleConfig) -> ImageResult<Vec<u8>> {
+        match self {
+            TileFormat::Png => Ok(png_data.to_vec()),
+            TileFormat::Webp => {
+                let image = DynamicImage::ImageRgba8(image::load_from_memory(png_data)?.to_rgba8());
+                let encoder =
+                    webp::Encoder::from_rgba(image.as_bytes(), image.width(), image.height());
+                Ok(encoder.encode(style.webp_quality as f32).to_vec())
+            }
+            TileFormat::Jpeg => {
+                let mut output = vec![];
+                // JPEG has no transparency
+                let image = DynamicImage::ImageRgb8(image::load_from_memory(png_data)?.to_rgb8());
+                JpegEncoder::new_with_quality(Cursor::new(&mut output), style.jpeg_quality)
+                    .encode_image(&image)?;
+                Ok(output)
+            }
+        }
+    }
+}
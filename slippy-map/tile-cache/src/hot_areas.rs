@@ -0,0 +1,328 @@
+//! Learning which areas are viewed the most, and keeping them fresh in the store.
+//!
+//! Every tile request is counted against the area containing it, which is its ancestor
+//! at `area_zoom`. Every night, the most viewed areas are downloaded again
+//! along with the areas around them, and the counts are halved so that they follow
+//! where the map is looked at lately.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::extract::State;
+
+use crate::{
+    config::{HotAreasConfig, PrecachePolicy, StyleConfig},
+    precache::adjacent_tiles,
+    tiles::{get_tile, refresh_tile},
+    AppState, TileId,
+};
+
+/// How often the counts are written to disk between nightly runs
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Areas viewed only once are forgotten when this many are counted
+const MAX_TRACKED_AREAS: usize = 100_000;
+
+/// Number of views of each area, by style
+pub struct AccessCounts {
+    area_zoom: u8,
+    file: Option<PathBuf>,
+    counts: Mutex<HashMap<(String, u32, u32), u64>>,
+    changed: AtomicBool,
+}
+
+impl AccessCounts {
+    /// Counts that are kept in `file` if given, starting with what it has
+    pub fn new(area_zoom: u8, file: Option<PathBuf>) -> Self {
+        let counts = match file {
+            Some(ref file) => match load_counts(file, area_zoom) {
+                Ok(counts) => counts,
+                Err(why) if why.kind() == io::ErrorKind::NotFound => HashMap::new(),
+                Err(why) => {
+                    tracing::warn!(
+                        "Could not load access counts from {}: {why}",
+                        file.display()
+                    );
+                    HashMap::new()
+                }
+            },
+            None => HashMap::new(),
+        };
+        AccessCounts {
+            area_zoom,
+            file,
+            counts: Mutex::new(counts),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Count a view of the tile; tiles above the area zoom are too coarse to say where one looks
+    pub fn record(&self, style: &str, tile: TileId) {
+        if tile.zoom < self.area_zoom {
+            return;
+        }
+        // Tiles off the map are in no area
+        let side = 1u64.checked_shl(tile.zoom.into()).unwrap_or(0);
+        if u64::from(tile.x) >= side || u64::from(tile.y) >= side {
+            return;
+        }
+        // Shifting a `u32` by 32 or more leaves nothing, but would overflow
+        let shift = (tile.zoom - self.area_zoom).into();
+        let ancestor = |n: u32| n.checked_shr(shift).unwrap_or(0);
+        let key = (style.to_string(), ancestor(tile.x), ancestor(tile.y));
+
+        let mut counts = self.counts.lock().unwrap();
+        if counts.len() >= MAX_TRACKED_AREAS && !counts.contains_key(&key) {
+            // Forget the areas seen only once so far
+            counts.retain(|_, count| *count > 1);
+            if counts.len() >= MAX_TRACKED_AREAS {
+                return;
+            }
+        }
+        *counts.entry(key).or_default() += 1;
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// The most viewed areas, as tiles at the area zoom, with their view counts
+    pub fn top(&self, limit: usize) -> Vec<(String, TileId, u64)> {
+        let counts = self.counts.lock().unwrap();
+        let mut areas: Vec<_> = counts
+            .iter()
+            .map(|((style, x, y), count)| {
+                (style.clone(), TileId::new(self.area_zoom, *x, *y), *count)
+            })
+            .collect();
+        areas.sort_by(|a, b| {
+            b.2.cmp(&a.2)
+                .then_with(|| (&a.0, a.1.x, a.1.y).cmp(&(&b.0, b.1.x, b.1.y)))
+        });
+        areas.truncate(limit);
+        areas
+    }
+
+    /// Halve all counts, so that old views matter less than recent ones
+    pub fn decay(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the counts to their file, if they have one and have changed
+    pub fn save(&self) -> io::Result<()> {
+        let Some(ref file) = self.file else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut text = String::new();
+        for ((style, x, y), count) in self.counts.lock().unwrap().iter() {
+            text.push_str(&format!("{style}\t{}\t{x}\t{y}\t{count}\n", self.area_zoom));
+        }
+        let result = write_counts(file, &text);
+        if result.is_err() {
+            // Try again next time
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+fn write_counts(file: &Path, text: &str) -> io::Result<()> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp_path = file.with_extension("tmp");
+    std::fs::write(&tmp_path, text)?;
+    std::fs::rename(&tmp_path, file)
+}
+
+/// Reads lines of `style, zoom, x, y, count` separated by tabs.
+/// Counts kept at a higher zoom are added up into their areas,
+/// and those at a lower zoom are dropped as they cannot be split.
+/// Lines that cannot be read are skipped with a warning.
+fn load_counts(file: &Path, area_zoom: u8) -> io::Result<HashMap<(String, u32, u32), u64>> {
+    let text = std::fs::read_to_string(file)?;
+    let mut counts = HashMap::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [style, zoom, x, y, count] = fields[..] else {
+            tracing::warn!("Skipping bad line in {}: {line}", file.display());
+            continue;
+        };
+        let (Ok(zoom), Ok(x), Ok(y), Ok(count)) = (
+            zoom.parse::<u8>(),
+            x.parse::<u32>(),
+            y.parse::<u32>(),
+            count.parse::<u64>(),
+        ) else {
+            tracing::warn!("Skipping bad line in {}: {line}", file.display());
+            continue;
+        };
+        let Some(shift) = zoom.checked_sub(area_zoom) else {
+            continue;
+        };
+        // Zooms that deep are not served, so such a line was not written by tile-cache
+        let (Some(x), Some(y)) = (x.checked_shr(shift.into()), y.checked_shr(shift.into())) else {
+            tracing::warn!("Skipping line with bad zoom in {}: {line}", file.display());
+            continue;
+        };
+        *counts.entry((style.to_string(), x, y)).or_default() += count;
+    }
+    Ok(counts)
+}
+
+/// Download the most viewed areas again, and the missing tiles around and below them
+pub async fn refresh_hot_areas(state: &AppState) -> String {
+    if !state.source.is_online() {
+        return "Tile source is offline, cannot perform any fetch".to_string();
+    }
+    let config = &state.config.hot_areas;
+
+    let mut areas = 0;
+    let mut refreshed = 0;
+    let mut new = 0;
+    let mut errors = 0;
+    for (style, area, count) in state.access_counts.top(config.top_areas) {
+        let Some(style_config) = state.config.style(&style) else {
+            continue;
+        };
+        tracing::info!("Refreshing hot area {style}/{area}, viewed {count} times");
+        areas += 1;
+
+        let mut idx_loop = style_config.subdomains.iter().cycle();
+        for tile in area_tiles(config, style_config, area) {
+            let idx = idx_loop.next().map(String::as_str).unwrap_or("a");
+            // Tiles of the area itself are replaced, the ones around are only filled in
+            let result = if contains(area, tile) {
+                refresh_tile(state, &style, idx, tile).await.map(|_| true)
+            } else {
+                get_tile(state, &style, idx, tile)
+                    .await
+                    .map(|(_, was_stored)| !was_stored)
+            };
+            match result {
+                Err(why) => {
+                    tracing::error!("Error: {why}");
+                    errors += 1;
+                }
+                Ok(true) if contains(area, tile) => refreshed += 1,
+                Ok(true) => new += 1,
+                Ok(false) => {}
+            }
+        }
+    }
+
+    state.access_counts.decay();
+    if let Err(why) = state.access_counts.save() {
+        tracing::error!("Could not save access counts: {why}");
+    }
+
+    format!("Areas: {areas}, errors: {errors}, refreshed tiles: {refreshed}, new tiles: {new}")
+}
+
+/// The area's tile, the tiles in a ring around it, and their subtiles
+fn area_tiles(config: &HotAreasConfig, style: &StyleConfig, area: TileId) -> Vec<TileId> {
+    let around = PrecachePolicy {
+        ancestors: false,
+        levels_down: 0,
+        neighbor_ring: config.ring,
+    };
+    let below = PrecachePolicy {
+        ancestors: false,
+        levels_down: config.levels_down,
+        neighbor_ring: 0,
+    };
+
+    let mut tiles = vec![];
+    for tile in std::iter::once(area).chain(adjacent_tiles(&around, style, area)) {
+        tiles.push(tile);
+        tiles.extend(adjacent_tiles(&below, style, tile));
+    }
+    tiles
+}
+
+/// Whether `tile` is `area` or one of its subtiles
+fn contains(area: TileId, tile: TileId) -> bool {
+    let Some(shift) = tile.zoom.checked_sub(area.zoom) else {
+        return false;
+    };
+    let shift = shift.into();
+    (tile.x.checked_shr(shift), tile.y.checked_shr(shift)) == (Some(area.x), Some(area.y))
+}
+
+/// Save the counts regularly, and refresh the hot areas every night at `run_at` UTC
+pub async fn run_nightly(state: AppState) {
+    let Some(run_at) = parse_time_of_day(&state.config.hot_areas.run_at) else {
+        tracing::error!(
+            "Invalid hot_areas.run_at `{}`, must look like `03:00`",
+            state.config.hot_areas.run_at
+        );
+        return;
+    };
+
+    let mut next_run = next_time_of_day(SystemTime::now(), run_at);
+    loop {
+        let until_run = next_run
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        tokio::time::sleep(until_run.min(SAVE_INTERVAL)).await;
+
+        if SystemTime::now() >= next_run {
+            if state.config.precache_enabled {
+                let report = refresh_hot_areas(&state).await;
+                tracing::info!("Nightly hot area refresh: {report}");
+            }
+            next_run = next_time_of_day(SystemTime::now(), run_at);
+        }
+        let counts = state.access_counts.clone();
+        let saved = tokio::task::spawn_blocking(move || counts.save()).await;
+        if let Ok(Err(why)) = saved {
+            tracing::error!("Could not save access counts: {why}");
+        }
+    }
+}
+
+/// Seconds since midnight of a time like `03:00`
+fn parse_time_of_day(text: &str) -> Option<u64> {
+    let (hours, minutes) = text.split_once(':')?;
+    let (hours, minutes): (u64, u64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 3600 + minutes * 60)
+}
+
+/// The first moment after `now` that is `time_of_day` seconds past a UTC midnight
+fn next_time_of_day(now: SystemTime, time_of_day: u64) -> SystemTime {
+    let now_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let midnight = now_secs - now_secs % 86400;
+    let mut next = midnight + time_of_day;
+    if next <= now_secs {
+        next += 86400;
+    }
+    UNIX_EPOCH + Duration::from_secs(next)
+}
+
+/// List the most viewed areas, one `style zoom/x/y views` per line
+pub async fn list_hot_areas(State(state): State<AppState>) -> String {
+    state
+        .access_counts
+        .top(state.config.hot_areas.top_areas)
+        .into_iter()
+        .map(|(style, area, count)| format!("{style} {area} {count}\n"))
+        .collect()
+}
+
+pub async fn refresh_hot_areas_now(State(state): State<AppState>) -> String {
+    refresh_hot_areas(&state).await
+}
@@ -0,0 +1,304 @@
+//! Caching proxy for slippy map tiles.
+//!
+//! Tiles are looked up in a [`TileStore`]; the missing ones are produced by a [`TileSource`]
+//! and stored for next time. [`RouterBuilder`] puts these together into an axum [`Router`].
+
+mod admin;
+pub mod config;
+mod etag;
+pub mod format;
+mod hot_areas;
+mod precache;
+mod rate_limit;
+mod render;
+mod snapshot;
+pub mod source;
+mod static_map;
+pub mod store;
+mod tilejson;
+mod tiles;
+mod wmts;
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use axum::{
+    http::{header, HeaderValue, Method},
+    middleware,
+    routing::get,
+    Router,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+pub use crate::{config::Config, format::TileFormat, source::TileSource, store::TileStore};
+use crate::{
+    config::{StoreKind, StyleConfig},
+    hot_areas::AccessCounts,
+    rate_limit::RateLimiter,
+    store::DirectoryStore,
+};
+
+/// Largest pixel density that can be requested, as in `@4x`
+pub const MAX_SCALE: u8 = 4;
+
+/// Deepest zoom whose columns and rows, counted either way, fit into a `u32`
+pub const MAX_ZOOM: u8 = 31;
+
+/// Position of a tile in the XYZ scheme, its pixel density and image format.
+///
+/// A tile at scale 2 covers the same area as at scale 1, but is 512px instead of 256px.
+/// Tiles at different scales and in different formats are stored separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub zoom: u8,
+    pub x: u32,
+    pub y: u32,
+    pub scale: u8,
+    pub format: TileFormat,
+}
+
+impl TileId {
+    pub fn new(zoom: u8, x: u32, y: u32) -> Self {
+        TileId {
+            zoom,
+            x,
+            y,
+            scale: 1,
+            format: TileFormat::Png,
+        }
+    }
+
+    pub fn with_scale(self, scale: u8) -> Self {
+        TileId { scale, ..self }
+    }
+
+    pub fn with_format(self, format: TileFormat) -> Self {
+        TileId { format, ..self }
+    }
+
+    /// Side of the tile's image in pixels
+    pub fn size(&self) -> u32 {
+        256 * self.scale as u32
+    }
+
+    /// Row of the tile counting from the bottom of the map, as in TMS and MBTiles.
+    /// Flipping it again gives back the XYZ row. `None` for tiles off the map.
+    pub fn tms_y(&self) -> Option<u32> {
+        1u32.checked_shl(self.zoom.into())?
+            .checked_sub(1)?
+            .checked_sub(self.y)
+    }
+
+    /// Bing-style quadkey, with one digit from 0 to 3 per zoom level, like `120`
+    pub fn quadkey(&self) -> String {
+        (1..=self.zoom)
+            .rev()
+            .map(|level| {
+                let mask = 1 << (level - 1);
+                let digit = (self.x & mask != 0) as u8 + 2 * (self.y & mask != 0) as u8;
+                char::from(b'0' + digit)
+            })
+            .collect()
+    }
+}
+
+/// Formats as `zoom/x/y`, with an `@2x` suffix for scaled tiles
+impl fmt::Display for TileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.zoom, self.x, self.y)?;
+        if self.scale != 1 {
+            write!(f, "@{}x", self.scale)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    store: Arc<dyn TileStore>,
+    source: Arc<dyn TileSource>,
+    rate_limiter: Arc<RateLimiter>,
+    access_counts: Arc<AccessCounts>,
+}
+
+/// Builds the router serving tiles, TileJSON, WMTS and static maps,
+/// and the precaching and maintenance endpoints under `/admin`.
+///
+/// The router needs to be served with [`Router::into_make_service_with_connect_info`]
+/// for rate limiting to tell the clients apart.
+/// Building it starts the nightly refresh of hot areas, so must be done inside a Tokio runtime.
+///
+/// Unless overridden, the store is picked by the config,
+/// and tiles are downloaded from the web if the `online` feature is enabled.
+pub struct RouterBuilder {
+    config: Config,
+    store: Option<Arc<dyn TileStore>>,
+    source: Option<Arc<dyn TileSource>>,
+}
+
+impl RouterBuilder {
+    pub fn new(config: Config) -> Self {
+        RouterBuilder {
+            config,
+            store: None,
+            source: None,
+        }
+    }
+
+    pub fn store(mut self, store: impl TileStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    pub fn source(mut self, source: impl TileSource + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    pub fn build(self) -> Router {
+        let access_counts_file = access_counts_file(&self.config, self.store.is_some());
+        let store = self.store.unwrap_or_else(|| default_store(&self.config));
+        let source = self.source.unwrap_or_else(|| default_source(&self.config));
+        let cors = cors_layer(&self.config);
+        let state = AppState {
+            rate_limiter: Arc::new(RateLimiter::new(self.config.rate_limit.clone())),
+            access_counts: Arc::new(AccessCounts::new(
+                self.config.hot_areas.area_zoom,
+                access_counts_file,
+            )),
+            config: Arc::new(self.config),
+            store,
+            source,
+        };
+        if state.config.hot_areas.enabled {
+            tokio::spawn(hot_areas::run_nightly(state.clone()));
+        }
+
+        let admin = Router::new()
+            .route("/stats", get(tiles::store_stats))
+            .route(
+                "/precache-until-zoom/:style/:zoom",
+                get(precache::precache_until_zoom),
+            )
+            .route(
+                "/precache-moscow-until-zoom/:style/:zoom",
+                get(precache::precache_moscow_until_zoom),
+            )
+            .route("/snapshot/:style/:name", get(snapshot::create_snapshot))
+            .route("/hot-areas", get(hot_areas::list_hot_areas))
+            .route("/hot-areas/refresh", get(hot_areas::refresh_hot_areas_now))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_admin,
+            ));
+
+        let app = Router::new()
+            .route("/", get(|| async { "Slippy map tile server!" }))
+            .route("/styles.json", get(tilejson::styles_index))
+            .route("/:style/tiles.json", get(tilejson::style_tilejson))
+            .route("/static/:style", get(static_map::static_map))
+            .route("/:style/:idx/:zoom/:x/:y_png", get(tiles::fetch_tile))
+            .route(
+                "/tms/:style/:idx/:zoom/:x/:y_png",
+                get(tiles::fetch_tms_tile),
+            )
+            .route("/wmts", get(wmts::wmts_kvp))
+            .route(
+                "/wmts/1.0.0/WMTSCapabilities.xml",
+                get(wmts::wmts_capabilities),
+            )
+            .route(
+                "/wmts/:layer/:style/:tile_matrix_set/:tile_matrix/:tile_row/:tile_col",
+                get(wmts::wmts_tile),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit::rate_limit,
+            ))
+            .nest("/admin", admin)
+            .with_state(state);
+
+        match cors {
+            Some(cors) => app.layer(cors),
+            None => app,
+        }
+    }
+}
+
+fn default_store(config: &Config) -> Arc<dyn TileStore> {
+    match config.store {
+        StoreKind::Directory => {
+            Arc::new(DirectoryStore::new(&config.cache_dir).dedup(config.dedup))
+        }
+        StoreKind::Memory => Arc::new(store::MemoryStore::new()),
+        #[cfg(feature = "mbtiles")]
+        StoreKind::Mbtiles => {
+            Arc::new(store::MbtilesStore::new(&config.cache_dir).dedup(config.dedup))
+        }
+        #[cfg(not(feature = "mbtiles"))]
+        StoreKind::Mbtiles => {
+            panic!("Compiled without 'mbtiles' feature, cannot use MBTiles store")
+        }
+    }
+}
+
+/// Counts are kept next to the tiles, unless those are not kept either
+fn access_counts_file(config: &Config, has_custom_store: bool) -> Option<PathBuf> {
+    if config.hot_areas.file.is_some() {
+        return config.hot_areas.file.clone();
+    }
+    if has_custom_store || matches!(config.store, StoreKind::Memory) {
+        return None;
+    }
+    Some(config.cache_dir.join("access-counts.tsv"))
+}
+
+/// Styles with a local MBTiles file read it, the others are downloaded
+fn default_source(config: &Config) -> Arc<dyn TileSource> {
+    if !config.styles.iter().any(StyleConfig::reads_mbtiles) {
+        return remote_source();
+    }
+    #[cfg(feature = "mbtiles")]
+    return Arc::new(source::MbtilesSource::new(remote_source()));
+    #[cfg(not(feature = "mbtiles"))]
+    panic!("Compiled without 'mbtiles' feature, cannot read tiles from MBTiles files")
+}
+
+#[cfg(feature = "online")]
+fn remote_source() -> Arc<dyn TileSource> {
+    Arc::new(source::HttpSource::new())
+}
+
+#[cfg(not(feature = "online"))]
+fn remote_source() -> Arc<dyn TileSource> {
+    Arc::new(source::OfflineSource)
+}
+
+fn cors_layer(config: &Config) -> Option<CorsLayer> {
+    let origins = &config.cors_allow_origins;
+    if origins.is_empty() {
+        return None;
+    }
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .iter()
+                .map(|o| HeaderValue::from_str(o).expect("Invalid CORS origin in config")),
+        )
+    };
+    Some(
+        CorsLayer::new()
+            .allow_methods([Method::GET, Method::HEAD])
+            .allow_origin(allow_origin)
+            .expose_headers([header::ETAG])
+            // Replaces the `Vary` header set by handlers, so must include the one for tile formats
+            .vary([
+                header::ORIGIN,
+                header::ACCESS_CONTROL_REQUEST_METHOD,
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                header::ACCEPT,
+            ]),
+    )
+}
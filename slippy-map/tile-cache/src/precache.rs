@@ -0,0 +1,174 @@
+use axum::extract::{Path, State};
+
+use crate::{
+    config::{PrecachePolicy, StyleConfig},
+    tiles::get_tile,
+    AppState, TileFormat, TileId, MAX_ZOOM,
+};
+
+/// Each level down has four times as many tiles, so this is at most 340 tiles
+const MAX_LEVELS_DOWN: u8 = 4;
+/// A ring of 3 is 48 tiles
+const MAX_NEIGHBOR_RING: u8 = 3;
+
+pub async fn precache_until_zoom(
+    Path((style, zoom)): Path<(String, u8)>,
+    State(state): State<AppState>,
+) -> String {
+    precache_tiles(&state, &style, slippy_map_tiles::Tile::all_to_zoom(zoom)).await
+}
+
+pub async fn precache_moscow_until_zoom(
+    Path((style, zoom)): Path<(String, u8)>,
+    State(state): State<AppState>,
+) -> String {
+    let moscow = slippy_map_tiles::BBox::new(57.0, 36.0, 55.0, 38.0).unwrap();
+    let tiles = slippy_map_tiles::Tile::all_to_zoom(zoom).filter(|tile| {
+        if !tile.bbox().overlaps_bbox(&moscow) {
+            tracing::debug!("Skip {tile:?}");
+            return false;
+        }
+        true
+    });
+    precache_tiles(&state, &style, tiles).await
+}
+
+async fn precache_tiles(
+    state: &AppState,
+    style: &str,
+    tiles: impl Iterator<Item = slippy_map_tiles::Tile>,
+) -> String {
+    if !state.source.is_online() {
+        return "Tile source is offline, cannot perform any fetch".to_string();
+    }
+
+    let mut idx_loop = (0..).map(|i| ["a", "b", "c"][i % 3]);
+
+    let mut errors = 0;
+    let mut existing = 0;
+    let mut new = 0;
+
+    for tile in tiles {
+        let result = get_tile(
+            state,
+            style,
+            idx_loop.next().unwrap(),
+            TileId::new(tile.zoom(), tile.x(), tile.y()),
+        )
+        .await;
+        match result {
+            Err(e) => {
+                tracing::error!("Error: {e}");
+                errors += 1;
+            }
+            Ok(ans) => match ans {
+                (_, true) => {
+                    existing += 1;
+                }
+                (_, false) => {
+                    new += 1;
+                }
+            },
+        }
+    }
+
+    format!("Errors: {errors}, existing tiles: {existing}, new tiles: {new}")
+}
+
+/// Fetch the tiles around a requested one in the background, following the style's policy
+pub fn precache_adjacent_tiles(state: &AppState, style: &str, idx: &str, tile: TileId) {
+    if !state.config.precache_enabled || !state.source.is_online() {
+        return;
+    }
+    let Some(style_config) = state.config.style(style) else {
+        return;
+    };
+    let tiles = adjacent_tiles(&style_config.precache, style_config, tile);
+    if tiles.is_empty() {
+        return;
+    }
+
+    tracing::info!("Precaching {} tiles around {style}/{tile}", tiles.len());
+    for tile in tiles {
+        tokio::spawn({
+            let state = state.clone();
+            let style = style.to_string();
+            let idx = idx.to_string();
+            async move { get_tile(&state, &style, &idx, tile).await }
+        });
+    }
+}
+
+/// Tiles that the policy asks for around `tile` within the style's zooms, at the same scale, as PNG
+pub(crate) fn adjacent_tiles(
+    policy: &PrecachePolicy,
+    style: &StyleConfig,
+    tile: TileId,
+) -> Vec<TileId> {
+    // Tiles off the map have nothing around them; `Tile::new` itself overflows past `MAX_ZOOM`
+    if tile.zoom > style.maxzoom.min(MAX_ZOOM) {
+        return vec![];
+    }
+    let Some(_) = slippy_map_tiles::Tile::new(tile.zoom, tile.x, tile.y) else {
+        return vec![];
+    };
+    let tile = tile.with_format(TileFormat::Png);
+    let mut tiles = vec![];
+
+    if policy.ancestors {
+        let mut ancestor = tile;
+        while ancestor.zoom > style.minzoom {
+            ancestor = TileId {
+                zoom: ancestor.zoom - 1,
+                x: ancestor.x / 2,
+                y: ancestor.y / 2,
+                ..ancestor
+            };
+            tiles.push(ancestor);
+        }
+    }
+
+    let mut level = vec![tile];
+    for _ in 0..policy.levels_down.min(MAX_LEVELS_DOWN) {
+        if level[0].zoom >= style.maxzoom.min(MAX_ZOOM) {
+            break;
+        }
+        level = level
+            .iter()
+            .flat_map(|parent| {
+                let (x, y) = (parent.x * 2, parent.y * 2);
+                [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)].map(|(x, y)| TileId {
+                    zoom: parent.zoom + 1,
+                    x,
+                    y,
+                    ..*parent
+                })
+            })
+            .collect();
+        tiles.extend_from_slice(&level);
+    }
+
+    // The map repeats horizontally, but not vertically
+    let side = 1i64 << tile.zoom;
+    let ring = policy.neighbor_ring.min(MAX_NEIGHBOR_RING) as i64;
+    for dy in -ring..=ring {
+        for dx in -ring..=ring {
+            let y = tile.y as i64 + dy;
+            if y < 0 || y >= side {
+                continue;
+            }
+            let x = (tile.x as i64 + dx).rem_euclid(side);
+            let neighbor = TileId {
+                x: x as u32,
+                y: y as u32,
+                ..tile
+            };
+            // Wrapping around small zooms reaches the same tiles from both sides
+            if neighbor != tile && !tiles.contains(&neighbor) {
+                tiles.push(neighbor);
+            }
+        }
+    }
+
+    tiles
+}
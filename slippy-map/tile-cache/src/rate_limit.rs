@@ -0,0 +1,115 @@
+//! Per-client rate limiting of the public routes.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Instant,
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{config::RateLimitConfig, AppState};
+
+/// Clients are forgotten once there are this many and their buckets have refilled
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Token bucket for each client: it holds up to `burst` requests,
+/// and refills at `requests_per_second`
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a request from the client's bucket.
+    /// If it is empty, returns how many seconds until the next request is allowed.
+    fn check(&self, client: IpAddr) -> Result<(), u64> {
+        let rate = self.config.requests_per_second as f64;
+        let burst = self.config.burst.max(1) as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate).ceil() as u64)
+        }
+    }
+
+    fn client_ip<B>(&self, req: &Request<B>) -> Option<IpAddr> {
+        if self.config.trust_forwarded_for {
+            let forwarded = req
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|value| value.to_str().ok())
+                // Earlier entries come from the client and can be anything
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok());
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    }
+}
+
+pub async fn rate_limit<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let limiter = &state.rate_limiter;
+    if limiter.config.requests_per_second == 0 {
+        return next.run(req).await;
+    }
+    // Requests not coming from a socket, like in tests, have no client to limit
+    let Some(client) = limiter.client_ip(&req) else {
+        return next.run(req).await;
+    };
+
+    match limiter.check(client) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => {
+            tracing::warn!("Rate limiting {client}");
+            let mut resp = (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+            resp.headers_mut()
+                .insert("Retry-After", HeaderValue::from(retry_after.max(1)));
+            resp
+        }
+    }
+}
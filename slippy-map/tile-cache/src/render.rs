@@ -0,0 +1,111 @@
+use std::io::Cursor;
+
+use image::{ImageOutputFormat, RgbImage};
+
+#[cfg(feature = "debug-highlight-fresh")]
+pub fn mark_fresh(png_data: Vec<u8>) -> Vec<u8> {
+    let image = image::load_from_memory(&png_data).unwrap();
+    let angle: i32 = rand::random();
+    let angle = angle % 360;
+    let mut image = image::imageops::huerotate(&image, angle);
+    image::imageops::invert(&mut image);
+
+    let size = image.width().min(image.height());
+    for i in 0..size {
+        for offset in 0..2 {
+            image[(offset, i)] = image::Rgba([255, 0, 255, 255]);
+            image[(i, offset)] = image::Rgba([255, 0, 255, 255]);
+            image[(size - 1 - offset, i)] = image::Rgba([255, 0, 255, 255]);
+            image[(i, size - 1 - offset)] = image::Rgba([255, 0, 255, 255]);
+        }
+    }
+
+    let mut output: Vec<u8> = vec![];
+    let mut writer = std::io::BufWriter::new(Cursor::new(&mut output));
+    image.write_to(&mut writer, ImageOutputFormat::Png).unwrap();
+
+    drop(writer);
+    output
+}
+
+#[cfg(not(feature = "debug-highlight-fresh"))]
+pub fn mark_fresh(png_data: Vec<u8>) -> Vec<u8> {
+    png_data
+}
+
+/// Draw the text on a tile of the given pixel density
+pub fn render_error_image(text: &str, scale: u8) -> Vec<u8> {
+    let scale = scale as u32;
+    let size = 256 * scale;
+
+    // Start by creating an image, fill it with a magenta-black pattern
+    let mut image = RgbImage::from_fn(size, size, |x, y| {
+        if (x / (16 * scale) + y / (16 * scale)).is_multiple_of(2) {
+            image::Rgb([127, 0, 127])
+        } else {
+            image::Rgb([0, 0, 0])
+        }
+    });
+
+    // Draw a bright magenta border
+    for i in 0..size {
+        for offset in 0..4 * scale {
+            image[(offset, i)] = image::Rgb([255, 0, 255]);
+            image[(i, offset)] = image::Rgb([255, 0, 255]);
+            image[(size - 1 - offset, i)] = image::Rgb([255, 0, 255]);
+            image[(i, size - 1 - offset)] = image::Rgb([255, 0, 255]);
+        }
+    }
+
+    // Draw some text on it
+    let font = load_font();
+    for (i, line) in textwrap::wrap(text, 20).iter().enumerate() {
+        image = imageproc::drawing::draw_text(
+            &image,
+            image::Rgb([255, 255, 255]),
+            0,
+            (24 * scale) as i32 * i as i32,
+            rusttype::Scale::uniform((24 * scale) as f32),
+            &font,
+            line,
+        );
+    }
+
+    // Save the image as a PNG
+    let mut output: Vec<u8> = vec![];
+    let mut writer = std::io::BufWriter::new(Cursor::new(&mut output));
+    image.write_to(&mut writer, ImageOutputFormat::Png).unwrap();
+
+    drop(writer);
+
+    // Export the PNG
+    output
+}
+
+pub fn load_font() -> rusttype::Font<'static> {
+    // Font: https://dejavu-fonts.github.io/Download.html
+    let font = include_bytes!("../DejaVuSans-Bold.ttf");
+    rusttype::Font::try_from_bytes(font).unwrap()
+}
+
+pub fn encode_png(image: impl Into<image::DynamicImage>) -> image::ImageResult<Vec<u8>> {
+    let mut output: Vec<u8> = vec![];
+    image
+        .into()
+        .write_to(&mut Cursor::new(&mut output), ImageOutputFormat::Png)?;
+    Ok(output)
+}
+
+/// Width and height of an encoded image, read from its header only
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+pub fn resize_tile(data: &[u8], size: u32) -> image::ImageResult<Vec<u8>> {
+    let image = image::load_from_memory(data)?;
+    encode_png(image.resize_exact(size, size, image::imageops::FilterType::CatmullRom))
+}
@@ -0,0 +1,209 @@
+//! Read-only snapshots of a style's stored tiles, to see the map as it was at some time.
+//!
+//! A snapshot is served as a style named `<style>@<name>`, like `_@2023-09`,
+//! from the tiles copied into it; nothing is ever downloaded for it.
+//! Copies share their contents with the live tiles when the store allows it.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    render,
+    static_map::{parse_floats, tile_range},
+    AppState, TileFormat, TileId, MAX_SCALE,
+};
+
+/// Separates the style from the snapshot's name in the style part of tile URLs
+pub(crate) const SEPARATOR: char = '@';
+
+/// Copying more tiles than this is refused, so that snapshots are made by region
+const MAX_SNAPSHOT_TILES: u64 = 1_000_000;
+
+/// First and last columns and rows at a zoom, as from [`tile_range`]
+type TileRange = ((u64, u64), (u64, u64));
+
+/// Split a style like `_@2023-09` into the live style and the snapshot's name
+pub fn parse(style: &str) -> Option<(&str, &str)> {
+    style.split_once(SEPARATOR)
+}
+
+/// Whether the style is a snapshot that has been made, like `_@2023-09`
+pub async fn exists(state: &AppState, style: &str) -> bool {
+    let Some((live_style, name)) = parse(style) else {
+        return false;
+    };
+    if !is_valid_name(name) || state.config.style(live_style).is_none() {
+        return false;
+    }
+    match state.store.has_style(style).await {
+        Ok(exists) => exists,
+        Err(why) => {
+            tracing::error!("Could not look for snapshot {style}: {why}");
+            false
+        }
+    }
+}
+
+/// Names may not clash with scale suffixes like `@2x`, nor make paths outside the store
+fn is_valid_name(name: &str) -> bool {
+    let looks_like_scale = name
+        .strip_suffix('x')
+        .is_some_and(|scale| scale.parse::<u8>().is_ok());
+    !name.is_empty()
+        && !looks_like_scale
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Query parameters of `/admin/snapshot/:style/:name`
+#[derive(Deserialize)]
+pub struct SnapshotQuery {
+    /// Region to copy, as `west,south,east,north`
+    bbox: String,
+    /// Defaults to the style's zooms
+    minzoom: Option<u8>,
+    maxzoom: Option<u8>,
+}
+
+/// Copy the stored tiles of a region into a snapshot, in the background.
+/// Tiles already in the snapshot are kept as they are, so a snapshot can be extended
+/// with more regions, but never changes.
+pub async fn create_snapshot(
+    Path((style, name)): Path<(String, String)>,
+    Query(query): Query<SnapshotQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(style_config) = state.config.style(&style) else {
+        return (StatusCode::NOT_FOUND, format!("Unknown style: {style}")).into_response();
+    };
+    if !is_valid_name(&name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Snapshot names may only have letters, digits, `-` and `_`, like `2023-09`",
+        )
+            .into_response();
+    }
+    let Some(bbox) = parse_floats::<4>(&query.bbox) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "`bbox` must look like `west,south,east,north`",
+        )
+            .into_response();
+    };
+    let minzoom = query.minzoom.unwrap_or(style_config.minzoom);
+    let maxzoom = query.maxzoom.unwrap_or(style_config.maxzoom);
+
+    let zooms = minzoom.max(style_config.minzoom)..=maxzoom.min(style_config.maxzoom);
+    let ranges: Vec<_> = zooms.map(|zoom| (zoom, tile_range(bbox, zoom))).collect();
+    let count: u64 = ranges
+        .iter()
+        .map(|(_, ((min_x, min_y), (max_x, max_y)))| {
+            (max_x + 1).saturating_sub(*min_x) * (max_y + 1).saturating_sub(*min_y)
+        })
+        .sum();
+    if count > MAX_SNAPSHOT_TILES {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("The region has {count} tiles, more than {MAX_SNAPSHOT_TILES}; pick a smaller `bbox` or `maxzoom`"),
+        )
+            .into_response();
+    }
+
+    let snapshot_style = format!("{style}{SEPARATOR}{name}");
+    tracing::info!("Copying up to {count} tiles into snapshot {snapshot_style}");
+    tokio::spawn(async move {
+        let report = copy_tiles(&state, &style, &snapshot_style, ranges).await;
+        tracing::info!("Snapshot {snapshot_style} is made: {report}");
+    });
+
+    (
+        StatusCode::ACCEPTED,
+        format!("Copying up to {count} tiles in the background"),
+    )
+        .into_response()
+}
+
+/// Copy the tiles in the ranges of columns and rows at each zoom, at all scales
+async fn copy_tiles(
+    state: &AppState,
+    style: &str,
+    snapshot_style: &str,
+    ranges: Vec<(u8, TileRange)>,
+) -> String {
+    let mut copied = 0;
+    let mut skipped = 0;
+    let mut errors = 0;
+    for (zoom, ((min_x, min_y), (max_x, max_y))) in ranges {
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                // Regular tiles, and those from providers with high density tiles
+                for scale in 1..=MAX_SCALE {
+                    let tile = TileId::new(zoom, x as u32, y as u32).with_scale(scale);
+                    match state.store.copy(style, snapshot_style, tile).await {
+                        Ok(true) => copied += 1,
+                        Ok(false) => skipped += 1,
+                        Err(why) => {
+                            tracing::error!("Could not copy tile {style}/{tile}: {why}");
+                            errors += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    format!("Errors: {errors}, copied tiles: {copied}, missing or already copied tiles: {skipped}")
+}
+
+/// The tile as it was when the snapshot was made. Other scales and formats
+/// than what was copied are made from the PNG, but not stored into the snapshot.
+pub async fn get_snapshot_tile(
+    state: &AppState,
+    style: &str,
+    tile: TileId,
+) -> Result<Vec<u8>, String> {
+    let Some((live_style, _)) = parse(style).filter(|(_, name)| is_valid_name(name)) else {
+        return Err(format!("Not a snapshot: {style}"));
+    };
+    let Some(style_config) = state.config.style(live_style) else {
+        return Err(format!("Unknown style: {live_style}"));
+    };
+    let load = |tile: TileId| async move {
+        state
+            .store
+            .get(style, tile)
+            .await
+            .map_err(|why| format!("Could not read tile {style}/{tile}\n{why}"))
+    };
+
+    if let Some(data) = load(tile).await? {
+        return Ok(data);
+    }
+    let png_tile = tile.with_format(TileFormat::Png);
+    let missing = || format!("Tile {style}/{tile} is not in the snapshot");
+    let png_data = match load(png_tile).await? {
+        Some(data) => data,
+        None if tile.scale == 1 => return Err(missing()),
+        None => {
+            let Some(base_data) = load(png_tile.with_scale(1)).await? else {
+                return Err(missing());
+            };
+            render::resize_tile(&base_data, tile.size())
+                .map_err(|why| format!("Could not upscale tile {style}/{tile}\n{why}"))?
+        }
+    };
+    if tile.format == TileFormat::Png {
+        return Ok(png_data);
+    }
+
+    let style_config = style_config.clone();
+    tokio::task::spawn_blocking(move || tile.format.convert(&png_data, &style_config))
+        .await
+        .map_err(|why| format!("Tile conversion task failed\n{why}"))?
+        .map_err(|why| format!("Could not convert tile {style}/{tile}\n{why}"))
+}
@@ -0,0 +1,233 @@
+//! Where tiles come from when they are not in the store yet.
+
+use std::sync::Arc;
+#[cfg(feature = "mbtiles")]
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use image::RgbImage;
+
+#[cfg(feature = "mbtiles")]
+use crate::config::TileScheme;
+use crate::{config::StyleConfig, render, TileId};
+
+#[async_trait]
+pub trait TileSource: Send + Sync {
+    /// Produce the image of a tile that is not stored yet.
+    /// `idx` is the subdomain that the client picked.
+    async fn fetch(&self, style: &StyleConfig, idx: &str, tile: TileId) -> Result<Vec<u8>, String>;
+
+    /// Whether this source can produce any tiles; if not, precaching is skipped
+    fn is_online(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl<T: TileSource + ?Sized> TileSource for Arc<T> {
+    async fn fetch(&self, style: &StyleConfig, idx: &str, tile: TileId) -> Result<Vec<u8>, String> {
+        (**self).fetch(style, idx, tile).await
+    }
+
+    fn is_online(&self) -> bool {
+        (**self).is_online()
+    }
+}
+
+/// Downloads tiles from the style's upstream URL
+#[cfg(feature = "online")]
+#[derive(Clone, Default)]
+pub struct HttpSource {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "online")]
+impl HttpSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "online")]
+#[async_trait]
+impl TileSource for HttpSource {
+    async fn fetch(&self, style: &StyleConfig, idx: &str, tile: TileId) -> Result<Vec<u8>, String> {
+        let name = &style.name;
+        let Some(url) = style.tile_url(idx, tile) else {
+            return Err(format!("Tile {name}/{tile} is off the map"));
+        };
+        // Try fetching the tile image from the online map provider
+        let resp = self.client.get(url).header("Referer", "http://leaflet-extras.github.io").header("User-Agent","pothole-detection-frontend/0.1, +https://github.com/imaginary-units-pfur/pothole-detection-frontend").send().await;
+        let resp = match resp.and_then(|resp| resp.error_for_status()) {
+            Ok(resp) => resp,
+            Err(why) => return Err(format!("Could not fetch tile {name}/{tile}\n{why}")),
+        };
+        match resp.bytes().await {
+            Ok(body) => Ok(body.to_vec()),
+            Err(why) => Err(format!("Could not fetch tile {name}/{tile}\n{why}")),
+        }
+    }
+}
+
+/// Reads tiles of styles with an `mbtiles:<path>` upstream URL from that local file,
+/// and gets the tiles of other styles from the fallback source.
+///
+/// The files are only read. Their rows count from the bottom as in the MBTiles spec,
+/// unless the style's `scheme` is `xyz`.
+#[cfg(feature = "mbtiles")]
+pub struct MbtilesSource {
+    fallback: Arc<dyn TileSource>,
+    files: Mutex<HashMap<PathBuf, Arc<Mutex<rusqlite::Connection>>>>,
+}
+
+#[cfg(feature = "mbtiles")]
+impl MbtilesSource {
+    pub fn new(fallback: impl TileSource + 'static) -> Self {
+        MbtilesSource {
+            fallback: Arc::new(fallback),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn connection(&self, path: &Path) -> Result<Arc<Mutex<rusqlite::Connection>>, String> {
+        use rusqlite::OptionalExtension;
+
+        let mut files = self.files.lock().unwrap();
+        if let Some(conn) = files.get(path) {
+            return Ok(conn.clone());
+        }
+        let conn =
+            rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .map_err(|why| format!("Could not open {}\n{why}", path.display()))?;
+        let format: Option<String> = conn
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'format'",
+                (),
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|why| format!("Could not read metadata of {}\n{why}", path.display()))?;
+        if format.as_deref() == Some("pbf") {
+            return Err(format!(
+                "{} has vector tiles, only raster tiles can be served",
+                path.display()
+            ));
+        }
+        let conn = Arc::new(Mutex::new(conn));
+        files.insert(path.to_path_buf(), conn.clone());
+        Ok(conn)
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+#[async_trait]
+impl TileSource for MbtilesSource {
+    async fn fetch(&self, style: &StyleConfig, idx: &str, tile: TileId) -> Result<Vec<u8>, String> {
+        let Some(path) = style.mbtiles_path(tile.scale) else {
+            return self.fallback.fetch(style, idx, tile).await;
+        };
+        let name = &style.name;
+        let row = match style.upstream_scheme(tile.scale) {
+            TileScheme::Tms => tile
+                .tms_y()
+                .ok_or_else(|| format!("Tile {name}/{tile} is off the map"))?,
+            TileScheme::Xyz => tile.y,
+            TileScheme::Quadkey => {
+                return Err(format!(
+                    "MBTiles files have no quadkeys, style {name} must use the tms or xyz scheme"
+                ))
+            }
+        };
+        let conn = self.connection(path)?;
+        let tile_data = tokio::task::spawn_blocking(move || {
+            use rusqlite::OptionalExtension;
+            conn.lock()
+                .unwrap()
+                .query_row(
+                    "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                    (tile.zoom, tile.x, row),
+                    |row| row.get(0),
+                )
+                .optional()
+        })
+        .await
+        .map_err(|why| format!("Tile reading task failed\n{why}"))?
+        .map_err(|why| format!("Could not read tile {name}/{tile} from {}\n{why}", path.display()))?;
+        tile_data.ok_or_else(|| format!("Tile {name}/{tile} is not in {}", path.display()))
+    }
+
+    /// Local files are not an upstream to fetch from in advance, only the fallback can be
+    fn is_online(&self) -> bool {
+        self.fallback.is_online()
+    }
+}
+
+/// Never produces any tiles, so only what is already stored gets served
+#[derive(Clone, Copy, Default)]
+pub struct OfflineSource;
+
+#[async_trait]
+impl TileSource for OfflineSource {
+    async fn fetch(
+        &self,
+        style: &StyleConfig,
+        _idx: &str,
+        tile: TileId,
+    ) -> Result<Vec<u8>, String> {
+        let name = &style.name;
+        tracing::error!("Tile {name}/{tile} not already stored, and the tile source is offline");
+        Err(format!("Tile {name}/{tile} is not cached\nWill not attempt fetching from web\nEnable 'online' feature for fetching"))
+    }
+
+    fn is_online(&self) -> bool {
+        false
+    }
+}
+
+/// Draws a grid with the tile's coordinates instead of a map,
+/// for trying out clients without access to any provider
+#[derive(Clone, Copy, Default)]
+pub struct SynthesizedSource;
+
+#[async_trait]
+impl TileSource for SynthesizedSource {
+    async fn fetch(
+        &self,
+        style: &StyleConfig,
+        _idx: &str,
+        tile: TileId,
+    ) -> Result<Vec<u8>, String> {
+        let scale = tile.scale as u32;
+        let size = tile.size();
+        let mut image = RgbImage::from_fn(size, size, |x, y| {
+            if x % (64 * scale) == 0 || y % (64 * scale) == 0 {
+                image::Rgb([200, 200, 200])
+            } else {
+                image::Rgb([240, 240, 240])
+            }
+        });
+        for i in 0..size {
+            image[(0, i)] = image::Rgb([80, 80, 80]);
+            image[(i, 0)] = image::Rgb([80, 80, 80]);
+        }
+
+        let font = render::load_font();
+        for (i, line) in [style.name.clone(), tile.to_string()].iter().enumerate() {
+            imageproc::drawing::draw_text_mut(
+                &mut image,
+                image::Rgb([40, 40, 40]),
+                8 * scale as i32,
+                (8 + 24 * i as i32) * scale as i32,
+                rusttype::Scale::uniform(20.0 * scale as f32),
+                &font,
+                line,
+            );
+        }
+
+        render::encode_png(image).map_err(|why| format!("Could not encode tile {tile}\n{why}"))
+    }
+}
@@ -0,0 +1,371 @@
+use std::f64::consts::PI;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::{imageops, Rgba, RgbaImage};
+use imageproc::{drawing, rect::Rect};
+use serde::Deserialize;
+
+use crate::{
+    render::{self, load_font},
+    tiles::get_tile,
+    AppState, TileId,
+};
+
+const TILE_SIZE: u32 = 256;
+const MAX_IMAGE_SIDE: u32 = 2048;
+/// Latitude where Web Mercator maps end, north and south
+const MAX_LATITUDE: f64 = 85.051129;
+
+/// Query parameters of `/static/:style`.
+///
+/// The area is given either by `center` (`lat,lon`) and `zoom`,
+/// or by `bbox` (`west,south,east,north`), in which case the zoom is picked to fit it.
+#[derive(Deserialize)]
+pub struct StaticMapQuery {
+    center: Option<String>,
+    zoom: Option<u8>,
+    bbox: Option<String>,
+    /// Output size as `WIDTHxHEIGHT`
+    #[serde(default = "default_size")]
+    size: String,
+    /// Damage markers as `lat,lon,kind` separated by `;`,
+    /// where `kind` is one of `bump`, `crack`, `hole`, `patch`, `other`
+    markers: Option<String>,
+    #[serde(default)]
+    scalebar: bool,
+}
+
+fn default_size() -> String {
+    "512x512".to_string()
+}
+
+/// Position in pixels from the top left corner of the world map at a given zoom
+fn project(lat: f64, lon: f64, zoom: u8) -> (f64, f64) {
+    let world = TILE_SIZE as f64 * 2f64.powi(zoom as i32);
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.0) / 360.0 * world;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * world;
+    (x, y)
+}
+
+/// Whether the point is on the Web Mercator map; never for infinite or NaN coordinates
+fn is_on_map(lat: f64, lon: f64) -> bool {
+    (-MAX_LATITUDE..=MAX_LATITUDE).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+/// Columns and rows of the first and last tiles covering `[west, south, east, north]`
+pub fn tile_range(bounds: [f64; 4], zoom: u8) -> ((u64, u64), (u64, u64)) {
+    let [west, south, east, north] = bounds;
+    let last = (1u64 << zoom) - 1;
+    let (left, top) = project(north, west, zoom);
+    let (right, bottom) = project(south, east, zoom);
+    let to_tile = |pixels: f64| ((pixels / TILE_SIZE as f64).floor().max(0.0) as u64).min(last);
+    // The far edges belong to the tiles before them
+    let to_last_tile = |pixels: f64| {
+        ((pixels / TILE_SIZE as f64).ceil() as u64)
+            .saturating_sub(1)
+            .min(last)
+    };
+    (
+        (to_tile(left), to_tile(top)),
+        (to_last_tile(right), to_last_tile(bottom)),
+    )
+}
+
+pub fn parse_floats<const N: usize>(text: &str) -> Option<[f64; N]> {
+    let values: Vec<f64> = text
+        .split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+fn parse_size(text: &str) -> Option<(u32, u32)> {
+    let (w, h) = text.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+/// Find the center and the largest zoom at which the bbox fits into the image
+fn fit_bbox(bbox: [f64; 4], width: u32, height: u32, maxzoom: u8) -> ((f64, f64), u8) {
+    let [west, south, east, north] = bbox;
+    let center = ((south + north) / 2.0, (west + east) / 2.0);
+    let mut zoom = maxzoom;
+    while zoom > 0 {
+        let (x1, y1) = project(north, west, zoom);
+        let (x2, y2) = project(south, east, zoom);
+        if x2 - x1 <= width as f64 && y2 - y1 <= height as f64 {
+            break;
+        }
+        zoom -= 1;
+    }
+    (center, zoom)
+}
+
+fn marker_icon(kind: &str) -> Option<RgbaImage> {
+    let data: &[u8] = match kind {
+        "bump" => include_bytes!("../../../art/bump.png"),
+        "crack" => include_bytes!("../../../art/crack.png"),
+        "hole" => include_bytes!("../../../art/hole.png"),
+        "patch" => include_bytes!("../../../art/patch.png"),
+        "other" => include_bytes!("../../../art/other.png"),
+        _ => return None,
+    };
+    let icon = image::load_from_memory(data).ok()?;
+    // Same size as the markers on the frontend map
+    Some(imageops::resize(
+        &icon.to_rgba8(),
+        28,
+        32,
+        imageops::FilterType::Triangle,
+    ))
+}
+
+struct Marker {
+    lat: f64,
+    lon: f64,
+    icon: RgbaImage,
+}
+
+fn parse_markers(markers: &str) -> Result<Vec<Marker>, String> {
+    markers
+        .split(';')
+        .filter(|m| !m.is_empty())
+        .map(|marker| {
+            let mut parts = marker.splitn(3, ',');
+            let (lat, lon, kind) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(lat), Some(lon), kind) => (lat, lon, kind.unwrap_or("other")),
+                _ => return Err(format!("Marker must look like `lat,lon,kind`: {marker}")),
+            };
+            let (Ok(lat), Ok(lon)) = (lat.parse::<f64>(), lon.parse::<f64>()) else {
+                return Err(format!("Marker must look like `lat,lon,kind`: {marker}"));
+            };
+            if !is_on_map(lat, lon) {
+                return Err(format!("Marker is off the map: {marker}"));
+            }
+            let Some(icon) = marker_icon(kind) else {
+                return Err(format!("Unknown marker kind: {kind}"));
+            };
+            Ok(Marker { lat, lon, icon })
+        })
+        .collect()
+}
+
+fn draw_markers(image: &mut RgbaImage, markers: &[Marker], zoom: u8, origin: (f64, f64)) {
+    for marker in markers {
+        // The icon's anchor is at the middle of its bottom edge
+        let (x, y) = project(marker.lat, marker.lon, zoom);
+        let x = (x - origin.0) as i64 - marker.icon.width() as i64 / 2;
+        let y = (y - origin.1) as i64 - marker.icon.height() as i64;
+        imageops::overlay(image, &marker.icon, x, y);
+    }
+}
+
+fn draw_scalebar(image: &mut RgbaImage, lat: f64, zoom: u8) {
+    let meters_per_pixel =
+        40075016.686 * lat.to_radians().cos() / (TILE_SIZE as f64 * 2f64.powi(zoom as i32));
+
+    // Pick the longest round distance that fits into 100 pixels
+    let max_meters = meters_per_pixel * 100.0;
+    let magnitude = 10f64.powi(max_meters.log10().floor() as i32);
+    let meters = [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|m| *m <= max_meters)
+        .unwrap_or(magnitude);
+    let length = (meters / meters_per_pixel).round() as i32;
+    let label = if meters >= 1000.0 {
+        format!("{} km", meters / 1000.0)
+    } else {
+        format!("{meters} m")
+    };
+
+    let font = load_font();
+    let scale = rusttype::Scale { x: 14.0, y: 14.0 };
+    let (text_width, _) = drawing::text_size(scale, &font, &label);
+
+    let left = 8;
+    let bottom = image.height() as i32 - 8;
+    let black = Rgba([0, 0, 0, 255]);
+    drawing::draw_filled_rect_mut(
+        image,
+        Rect::at(left - 4, bottom - 24).of_size((length.max(text_width) + 8) as u32, 28),
+        Rgba([255, 255, 255, 200]),
+    );
+    drawing::draw_text_mut(image, black, left, bottom - 22, scale, &font, &label);
+    for offset in 0..2 {
+        let y = (bottom - offset) as f32;
+        drawing::draw_line_segment_mut(image, (left as f32, y), ((left + length) as f32, y), black);
+    }
+    for x in [left, left + length] {
+        drawing::draw_line_segment_mut(
+            image,
+            (x as f32, (bottom - 6) as f32),
+            (x as f32, bottom as f32),
+            black,
+        );
+    }
+}
+
+pub async fn static_map(
+    Path(style): Path<String>,
+    Query(query): Query<StaticMapQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(style_config) = state.config.style(&style) else {
+        return (StatusCode::NOT_FOUND, format!("Unknown style: {style}")).into_response();
+    };
+
+    let (width, height) = match parse_size(&query.size) {
+        Some((w, h)) if w > 0 && h > 0 && w <= MAX_IMAGE_SIDE && h <= MAX_IMAGE_SIDE => (w, h),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("`size` must look like `512x512`, at most {MAX_IMAGE_SIDE} on each side"),
+            )
+                .into_response()
+        }
+    };
+
+    // Checked before any tile is fetched
+    let markers = match query.markers.as_deref().map(parse_markers).transpose() {
+        Ok(markers) => markers.unwrap_or_default(),
+        Err(why) => return (StatusCode::BAD_REQUEST, why).into_response(),
+    };
+
+    let ((lat, lon), zoom) = match (&query.center, query.zoom, &query.bbox) {
+        (Some(center), Some(zoom), None) => match parse_floats::<2>(center) {
+            Some([lat, lon]) if !is_on_map(lat, lon) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("`center` must be on the map, within ±{MAX_LATITUDE}° of latitude and ±180° of longitude"),
+                )
+                    .into_response()
+            }
+            Some([lat, lon]) => (
+                (lat, lon),
+                zoom.clamp(style_config.minzoom, style_config.maxzoom),
+            ),
+            None => {
+                return (StatusCode::BAD_REQUEST, "`center` must look like `lat,lon`")
+                    .into_response()
+            }
+        },
+        (None, None, Some(bbox)) => match parse_floats::<4>(bbox) {
+            Some([west, south, east, north])
+                if !is_on_map(south, west) || !is_on_map(north, east) =>
+            {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("`bbox` must be on the map, within ±{MAX_LATITUDE}° of latitude and ±180° of longitude"),
+                )
+                    .into_response()
+            }
+            Some(bbox) => fit_bbox(bbox, width, height, style_config.maxzoom),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "`bbox` must look like `west,south,east,north`",
+                )
+                    .into_response()
+            }
+        },
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Give either `center` and `zoom`, or `bbox`",
+            )
+                .into_response()
+        }
+    };
+
+    // Pixel position of the image's top left corner in the world map
+    let (center_x, center_y) = project(lat, lon, zoom);
+    let origin = (
+        (center_x - width as f64 / 2.0).floor(),
+        (center_y - height as f64 / 2.0).floor(),
+    );
+
+    let tiles_per_side = 2i64.pow(zoom as u32);
+    let first_x = (origin.0 / TILE_SIZE as f64).floor() as i64;
+    let first_y = (origin.1 / TILE_SIZE as f64).floor() as i64;
+    let last_x = ((origin.0 + width as f64 - 1.0) / TILE_SIZE as f64).floor() as i64;
+    let last_y = ((origin.1 + height as f64 - 1.0) / TILE_SIZE as f64).floor() as i64;
+
+    let mut jobs = vec![];
+    for tile_x in first_x..=last_x {
+        for tile_y in first_y..=last_y {
+            if tile_y < 0 || tile_y >= tiles_per_side {
+                continue;
+            }
+            // The map repeats horizontally
+            let x = tile_x.rem_euclid(tiles_per_side) as u32;
+            let y = tile_y as u32;
+            let state = state.clone();
+            let style = style.clone();
+            let idx = match style_config.subdomains.len() {
+                0 => "a".to_string(),
+                n => {
+                    style_config.subdomains[(tile_x + tile_y).rem_euclid(n as i64) as usize].clone()
+                }
+            };
+            let job = tokio::spawn(async move {
+                get_tile(&state, &style, &idx, TileId::new(zoom, x, y)).await
+            });
+            jobs.push((tile_x, tile_y, job));
+        }
+    }
+
+    let mut image = RgbaImage::from_pixel(width, height, Rgba([221, 221, 221, 255]));
+    for (tile_x, tile_y, job) in jobs {
+        let tile = match job.await {
+            Ok(Ok((data, _))) => data,
+            Ok(Err(why)) => {
+                tracing::warn!("Static map is missing a tile: {why}");
+                continue;
+            }
+            Err(why) => {
+                tracing::error!("Tile fetching task failed: {why}");
+                continue;
+            }
+        };
+        let tile = match image::load_from_memory(&tile) {
+            Ok(tile) => tile.to_rgba8(),
+            Err(why) => {
+                tracing::warn!("Could not decode tile {style}/{zoom}/{tile_x}/{tile_y}: {why}");
+                continue;
+            }
+        };
+        imageops::overlay(
+            &mut image,
+            &tile,
+            tile_x * TILE_SIZE as i64 - origin.0 as i64,
+            tile_y * TILE_SIZE as i64 - origin.1 as i64,
+        );
+    }
+
+    draw_markers(&mut image, &markers, zoom, origin);
+
+    if query.scalebar {
+        draw_scalebar(&mut image, lat, zoom);
+    }
+
+    let output = match render::encode_png(image) {
+        Ok(output) => output,
+        Err(why) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Could not encode image: {why}"),
+            )
+                .into_response()
+        }
+    };
+
+    let mut resp = output.into_response();
+    resp.headers_mut()
+        .insert("Content-Type", HeaderValue::from_static("image/png"));
+    resp
+}
@@ -0,0 +1,702 @@
+//! Places where tiles are kept once they have been fetched.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::{TileFormat, TileId};
+
+#[async_trait]
+pub trait TileStore: Send + Sync {
+    /// Look up a tile, returning `None` if it is not stored yet
+    async fn get(&self, style: &str, tile: TileId) -> io::Result<Option<Vec<u8>>>;
+
+    async fn put(&self, style: &str, tile: TileId, data: &[u8]) -> io::Result<()>;
+
+    /// Copy a tile to another style, unless that style has it already.
+    /// Returns whether there was a tile to copy and it was copied.
+    /// Stores that can keep a single copy of the contents for both styles do so.
+    async fn copy(&self, from_style: &str, to_style: &str, tile: TileId) -> io::Result<bool> {
+        if self.get(to_style, tile).await?.is_some() {
+            return Ok(false);
+        }
+        match self.get(from_style, tile).await? {
+            Some(data) => {
+                self.put(to_style, tile, &data).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Whether any tile of the style has been stored.
+    /// Stores that cannot tell without a tile to look for say yes.
+    async fn has_style(&self, _style: &str) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Count the stored tiles and the space they take
+    async fn stats(&self) -> io::Result<StoreStats> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "This store does not report statistics",
+        ))
+    }
+}
+
+#[async_trait]
+impl<T: TileStore + ?Sized> TileStore for Arc<T> {
+    async fn get(&self, style: &str, tile: TileId) -> io::Result<Option<Vec<u8>>> {
+        (**self).get(style, tile).await
+    }
+
+    async fn put(&self, style: &str, tile: TileId, data: &[u8]) -> io::Result<()> {
+        (**self).put(style, tile, data).await
+    }
+
+    async fn copy(&self, from_style: &str, to_style: &str, tile: TileId) -> io::Result<bool> {
+        (**self).copy(from_style, to_style, tile).await
+    }
+
+    async fn has_style(&self, style: &str) -> io::Result<bool> {
+        (**self).has_style(style).await
+    }
+
+    async fn stats(&self) -> io::Result<StoreStats> {
+        (**self).stats().await
+    }
+}
+
+/// How many tiles are stored, and how much deduplication saved
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub tiles: u64,
+    /// Tiles with distinct contents; identical tiles are counted once if they are deduplicated
+    pub unique_tiles: u64,
+    /// Size of all tiles, as if each was stored separately
+    pub total_bytes: u64,
+    /// Size actually taken by the tiles
+    pub stored_bytes: u64,
+}
+
+impl StoreStats {
+    pub fn saved_bytes(&self) -> u64 {
+        self.total_bytes.saturating_sub(self.stored_bytes)
+    }
+}
+
+impl fmt::Display for StoreStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let saved_percent = match self.total_bytes {
+            0 => 0.0,
+            total => self.saved_bytes() as f64 * 100.0 / total as f64,
+        };
+        write!(
+            f,
+            "Tiles: {}, unique tiles: {}, total size: {} bytes, stored size: {} bytes, saved by deduplication: {} bytes ({saved_percent:.1}%)",
+            self.tiles,
+            self.unique_tiles,
+            self.total_bytes,
+            self.stored_bytes,
+            self.saved_bytes(),
+        )
+    }
+}
+
+/// Hex SHA-256 of the tile's contents, which identifies deduplicated tiles
+fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Write to a temporary file first and rename it into place,
+/// so that concurrent readers never see a partially written file
+async fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = tmp_path(path);
+    tokio::fs::write(&tmp_path, data).await?;
+    if let Err(why) = tokio::fs::rename(&tmp_path, path).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(why);
+    }
+    Ok(())
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", rand::random::<u32>()));
+    path.with_file_name(name)
+}
+
+/// Stores tiles as `<root>/<style>/<zoom>/<x>/<y>.png`, the same layout as the tile URLs.
+/// Other formats are kept next to the PNG, like `<y>.webp`.
+///
+/// With deduplication, the contents are kept once per distinct tile in `<root>/.blobs`,
+/// named by their hash, and the tile paths are hard links to them.
+pub struct DirectoryStore {
+    root: PathBuf,
+    dedup: bool,
+}
+
+impl DirectoryStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DirectoryStore {
+            root: root.into(),
+            dedup: false,
+        }
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    fn tile_path(&self, style: &str, tile: TileId) -> PathBuf {
+        self.root
+            .join(format!("{style}/{tile}.{}", tile.format.extension()))
+    }
+
+    fn blobs_dir(&self) -> PathBuf {
+        self.root.join(".blobs")
+    }
+
+    /// Store the contents once under their hash, and link the tile path to them
+    async fn put_deduplicated(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let hash = content_hash(data);
+        let blob_path = self.blobs_dir().join(&hash[..2]).join(&hash);
+        if !tokio::fs::try_exists(&blob_path).await? {
+            tokio::fs::create_dir_all(blob_path.parent().unwrap()).await?;
+            write_atomically(&blob_path, data).await?;
+        }
+
+        let tmp_path = tmp_path(path);
+        tokio::fs::hard_link(&blob_path, &tmp_path).await?;
+        let result = tokio::fs::rename(&tmp_path, path).await;
+        // Renaming onto another link to the same file leaves both in place
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        result
+    }
+}
+
+#[async_trait]
+impl TileStore for DirectoryStore {
+    async fn get(&self, style: &str, tile: TileId) -> io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.tile_path(style, tile)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(why),
+        }
+    }
+
+    async fn put(&self, style: &str, tile: TileId, data: &[u8]) -> io::Result<()> {
+        let path = self.tile_path(style, tile);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        if self.dedup {
+            match self.put_deduplicated(&path, data).await {
+                Ok(()) => return Ok(()),
+                Err(why) => {
+                    tracing::warn!(
+                        "Could not deduplicate tile {style}/{tile}, storing a copy: {why}"
+                    )
+                }
+            }
+        }
+        write_atomically(&path, data).await
+    }
+
+    /// Hard links the tile, as tiles are only ever replaced and never changed in place
+    async fn copy(&self, from_style: &str, to_style: &str, tile: TileId) -> io::Result<bool> {
+        let from_path = self.tile_path(from_style, tile);
+        let to_path = self.tile_path(to_style, tile);
+        if !tokio::fs::try_exists(&from_path).await? {
+            return Ok(false);
+        }
+        if let Some(dir) = to_path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        match tokio::fs::hard_link(&from_path, &to_path).await {
+            Ok(()) => Ok(true),
+            Err(why) if why.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            // Replaced or removed in the meantime
+            Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(why) => {
+                tracing::warn!(
+                    "Could not link tile {from_style}/{tile} to {to_style}, copying it: {why}"
+                );
+                let Some(data) = self.get(from_style, tile).await? else {
+                    return Ok(false);
+                };
+                write_atomically(&to_path, &data).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn has_style(&self, style: &str) -> io::Result<bool> {
+        tokio::fs::try_exists(self.root.join(style)).await
+    }
+
+    async fn stats(&self) -> io::Result<StoreStats> {
+        let root = self.root.clone();
+        let blobs_dir = self.blobs_dir();
+        tokio::task::spawn_blocking(move || {
+            let mut stats = StoreStats::default();
+            let mut seen_files = HashSet::new();
+            let mut dirs = vec![root];
+            while let Some(dir) = dirs.pop() {
+                let entries = match std::fs::read_dir(&dir) {
+                    Ok(entries) => entries,
+                    Err(why) if why.kind() == io::ErrorKind::NotFound => continue,
+                    Err(why) => return Err(why),
+                };
+                for entry in entries {
+                    let entry = entry?;
+                    let path = entry.path();
+                    let metadata = entry.metadata()?;
+                    if metadata.is_dir() {
+                        if path != blobs_dir {
+                            dirs.push(path);
+                        }
+                        continue;
+                    }
+                    // Skips temporary files, and anything else that is not a tile
+                    let is_tile = path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| TileFormat::from_extension(ext).is_some());
+                    if !is_tile {
+                        continue;
+                    }
+                    stats.tiles += 1;
+                    stats.total_bytes += metadata.len();
+                    // Hard links to the same blob are the same file
+                    let is_new = match file_id(&metadata) {
+                        Some(id) => seen_files.insert(id),
+                        None => true,
+                    };
+                    if is_new {
+                        stats.unique_tiles += 1;
+                        stats.stored_bytes += metadata.len();
+                    }
+                }
+            }
+            Ok(stats)
+        })
+        .await?
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without a portable file identity, every path is counted as a separate file
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Keeps tiles in memory only, for tests and short-lived servers.
+/// Identical tiles share the same buffer.
+#[derive(Default)]
+pub struct MemoryStore {
+    tiles: Mutex<MemoryTiles>,
+}
+
+#[derive(Default)]
+struct MemoryTiles {
+    index: HashMap<(String, TileId), Arc<[u8]>>,
+    blobs: HashSet<Arc<[u8]>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.lock().unwrap().index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl TileStore for MemoryStore {
+    async fn get(&self, style: &str, tile: TileId) -> io::Result<Option<Vec<u8>>> {
+        let tiles = self.tiles.lock().unwrap();
+        Ok(tiles
+            .index
+            .get(&(style.to_string(), tile))
+            .map(|data| data.to_vec()))
+    }
+
+    async fn put(&self, style: &str, tile: TileId, data: &[u8]) -> io::Result<()> {
+        let mut tiles = self.tiles.lock().unwrap();
+        let blob = match tiles.blobs.get(data) {
+            Some(blob) => blob.clone(),
+            None => {
+                let blob: Arc<[u8]> = data.into();
+                tiles.blobs.insert(blob.clone());
+                blob
+            }
+        };
+        if let Some(old) = tiles.index.insert((style.to_string(), tile), blob) {
+            // Only the set of blobs refers to it now
+            if Arc::strong_count(&old) == 2 {
+                tiles.blobs.remove(&old);
+            }
+        }
+        Ok(())
+    }
+
+    async fn has_style(&self, style: &str) -> io::Result<bool> {
+        let tiles = self.tiles.lock().unwrap();
+        Ok(tiles
+            .index
+            .keys()
+            .any(|(stored_style, _)| stored_style == style))
+    }
+
+    async fn stats(&self) -> io::Result<StoreStats> {
+        let tiles = self.tiles.lock().unwrap();
+        Ok(StoreStats {
+            tiles: tiles.index.len() as u64,
+            unique_tiles: tiles.blobs.len() as u64,
+            total_bytes: tiles.index.values().map(|data| data.len() as u64).sum(),
+            stored_bytes: tiles.blobs.iter().map(|data| data.len() as u64).sum(),
+        })
+    }
+}
+
+/// Stores each style in its own `<dir>/<style>.mbtiles` SQLite file,
+/// its scaled tiles in `<dir>/<style>@2x.mbtiles` and so on,
+/// and its tiles in other formats in `<dir>/<style>.webp.mbtiles` and so on,
+/// see https://github.com/mapbox/mbtiles-spec/blob/master/1.3/spec.md
+///
+/// With deduplication, new files use the `map` and `images` tables instead of `tiles`,
+/// which then is a view joining them; existing files keep their layout.
+#[cfg(feature = "mbtiles")]
+pub struct MbtilesStore {
+    dir: PathBuf,
+    dedup: bool,
+    connections: Mutex<HashMap<String, Arc<MbtilesFile>>>,
+}
+
+#[cfg(feature = "mbtiles")]
+struct MbtilesFile {
+    conn: Mutex<rusqlite::Connection>,
+    is_deduplicated: bool,
+    is_read_only: bool,
+}
+
+#[cfg(feature = "mbtiles")]
+impl MbtilesStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        MbtilesStore {
+            dir: dir.into(),
+            dedup: false,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Name of the file with the tile's style, scale and format, without `.mbtiles`
+    fn file_name(style: &str, tile: TileId) -> String {
+        let mut name = match tile.scale {
+            1 => style.to_string(),
+            scale => format!("{style}@{scale}x"),
+        };
+        if tile.format != TileFormat::Png {
+            name = format!("{name}.{}", tile.format.extension());
+        }
+        name
+    }
+
+    /// The file to read the tile from, or `None` if there is none yet.
+    /// Files are opened read-only until something is written to them,
+    /// so that reading never creates a file.
+    fn file_for_reading(&self, style: &str, tile: TileId) -> io::Result<Option<Arc<MbtilesFile>>> {
+        let name = Self::file_name(style, tile);
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(file) = connections.get(&name) {
+            return Ok(Some(file.clone()));
+        }
+
+        let path = self.dir.join(format!("{name}.mbtiles"));
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+        let file = Arc::new(open_mbtiles_read_only(&path).map_err(io::Error::other)?);
+        connections.insert(name, file.clone());
+        Ok(Some(file))
+    }
+
+    /// The file to write the tile into, created if needed
+    fn file_for_writing(&self, style: &str, tile: TileId) -> io::Result<Arc<MbtilesFile>> {
+        let name = Self::file_name(style, tile);
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(file) = connections.get(&name) {
+            if !file.is_read_only {
+                return Ok(file.clone());
+            }
+        }
+
+        std::fs::create_dir_all(&self.dir)?;
+        let file = open_mbtiles(
+            &self.dir.join(format!("{name}.mbtiles")),
+            &name,
+            tile.format,
+            self.dedup,
+        )
+        .map_err(io::Error::other)?;
+        let file = Arc::new(file);
+        connections.insert(name, file.clone());
+        Ok(file)
+    }
+
+    /// Whether the file name is one of the style's, like `_.mbtiles` or `_@2x.webp.mbtiles`
+    fn is_style_file(style: &str, file_name: &str) -> bool {
+        let Some(stem) = file_name.strip_suffix(".mbtiles") else {
+            return false;
+        };
+        let without_format = stem.split_once('.').map_or(stem, |(name, _)| name);
+        let Some(rest) = without_format.strip_prefix(style) else {
+            return false;
+        };
+        rest.is_empty()
+            || rest
+                .strip_prefix('@')
+                .and_then(|scale| scale.strip_suffix('x'))
+                .is_some_and(|scale| scale.parse::<u8>().is_ok())
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+fn open_mbtiles(
+    path: &Path,
+    style: &str,
+    format: TileFormat,
+    dedup: bool,
+) -> rusqlite::Result<MbtilesFile> {
+    use rusqlite::OptionalExtension;
+
+    let conn = rusqlite::Connection::open(path)?;
+    let tiles_type: Option<String> = conn
+        .query_row(
+            "SELECT type FROM sqlite_master WHERE name = 'tiles'",
+            (),
+            |row| row.get(0),
+        )
+        .optional()?;
+    let is_deduplicated = match tiles_type.as_deref() {
+        Some("view") => true,
+        Some(_) => false,
+        None => dedup,
+    };
+
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);")?;
+    if is_deduplicated {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS map (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_id TEXT);
+             CREATE UNIQUE INDEX IF NOT EXISTS map_index ON map (zoom_level, tile_column, tile_row);
+             CREATE TABLE IF NOT EXISTS images (tile_id TEXT, tile_data BLOB);
+             CREATE UNIQUE INDEX IF NOT EXISTS images_id ON images (tile_id);
+             CREATE VIEW IF NOT EXISTS tiles AS
+                 SELECT map.zoom_level AS zoom_level, map.tile_column AS tile_column,
+                        map.tile_row AS tile_row, images.tile_data AS tile_data
+                 FROM map JOIN images ON images.tile_id = map.tile_id;",
+        )?;
+    } else {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+             CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);",
+        )?;
+    }
+    for (name, value) in [("name", style), ("format", format.extension())] {
+        conn.execute(
+            "INSERT INTO metadata (name, value) SELECT ?1, ?2
+             WHERE NOT EXISTS (SELECT 1 FROM metadata WHERE name = ?1)",
+            (name, value),
+        )?;
+    }
+    Ok(MbtilesFile {
+        conn: Mutex::new(conn),
+        is_deduplicated,
+        is_read_only: false,
+    })
+}
+
+#[cfg(feature = "mbtiles")]
+fn open_mbtiles_read_only(path: &Path) -> rusqlite::Result<MbtilesFile> {
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let is_deduplicated: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'view' AND name = 'tiles'",
+        (),
+        |row| row.get(0),
+    )?;
+    Ok(MbtilesFile {
+        conn: Mutex::new(conn),
+        is_deduplicated,
+        is_read_only: true,
+    })
+}
+
+#[cfg(feature = "mbtiles")]
+fn mbtiles_stats(path: &Path) -> rusqlite::Result<StoreStats> {
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let (tiles, total_bytes): (u64, Option<u64>) = conn.query_row(
+        "SELECT COUNT(*), SUM(LENGTH(tile_data)) FROM tiles",
+        (),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let has_images: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'images'",
+        (),
+        |row| row.get(0),
+    )?;
+    let (unique_tiles, stored_bytes) = if has_images {
+        let (count, bytes): (u64, Option<u64>) = conn.query_row(
+            "SELECT COUNT(*), SUM(LENGTH(tile_data)) FROM images",
+            (),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        (count, bytes.unwrap_or(0))
+    } else {
+        (tiles, total_bytes.unwrap_or(0))
+    };
+    Ok(StoreStats {
+        tiles,
+        unique_tiles,
+        total_bytes: total_bytes.unwrap_or(0),
+        stored_bytes,
+    })
+}
+
+#[cfg(feature = "mbtiles")]
+#[async_trait]
+impl TileStore for MbtilesStore {
+    async fn get(&self, style: &str, tile: TileId) -> io::Result<Option<Vec<u8>>> {
+        let Some(row) = tile.tms_y() else {
+            return Ok(None);
+        };
+        let Some(file) = self.file_for_reading(style, tile)? else {
+            return Ok(None);
+        };
+        tokio::task::spawn_blocking(move || {
+            use rusqlite::OptionalExtension;
+            file.conn
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                    (tile.zoom, tile.x, row),
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(io::Error::other)
+        })
+        .await?
+    }
+
+    async fn put(&self, style: &str, tile: TileId, data: &[u8]) -> io::Result<()> {
+        let row = tile.tms_y().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Tile {style}/{tile} is off the map"),
+            )
+        })?;
+        let file = self.file_for_writing(style, tile)?;
+        let data = data.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = file.conn.lock().unwrap();
+            if !file.is_deduplicated {
+                return conn
+                    .execute(
+                        "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+                        (tile.zoom, tile.x, row, data),
+                    )
+                    .map(|_| ())
+                    .map_err(io::Error::other);
+            }
+
+            let hash = content_hash(&data);
+            let transaction = conn.transaction().map_err(io::Error::other)?;
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO images (tile_id, tile_data) VALUES (?1, ?2)",
+                    (&hash, data),
+                )
+                .map_err(io::Error::other)?;
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?1, ?2, ?3, ?4)",
+                    (tile.zoom, tile.x, row, &hash),
+                )
+                .map_err(io::Error::other)?;
+            transaction.commit().map_err(io::Error::other)
+        })
+        .await?
+    }
+
+    async fn has_style(&self, style: &str) -> io::Result<bool> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(why) => return Err(why),
+        };
+        for entry in entries {
+            if Self::is_style_file(style, &entry?.file_name().to_string_lossy()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Sums up all MBTiles files in the directory; tiles are only deduplicated within a file
+    async fn stats(&self) -> io::Result<StoreStats> {
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut stats = StoreStats::default();
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(stats),
+                Err(why) => return Err(why),
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "mbtiles") {
+                    continue;
+                }
+                let file_stats = mbtiles_stats(&path).map_err(io::Error::other)?;
+                stats.tiles += file_stats.tiles;
+                stats.unique_tiles += file_stats.unique_tiles;
+                stats.total_bytes += file_stats.total_bytes;
+                stats.stored_bytes += file_stats.stored_bytes;
+            }
+            Ok(stats)
+        })
+        .await?
+    }
+}
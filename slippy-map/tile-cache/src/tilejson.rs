@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Host, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{
+    config::{Config, StyleConfig},
+    AppState,
+};
+
+/// A TileJSON 3.0.0 document, see https://github.com/mapbox/tilejson-spec/tree/master/3.0.0
+#[derive(Serialize)]
+pub struct TileJson {
+    tilejson: &'static str,
+    /// Style identifier, usable in this server's tile URLs (not part of the spec)
+    id: String,
+    name: String,
+    attribution: String,
+    scheme: &'static str,
+    tiles: Vec<String>,
+    minzoom: u8,
+    maxzoom: u8,
+    bounds: [f64; 4],
+}
+
+impl TileJson {
+    pub fn new(style: &StyleConfig, base_url: &str) -> Self {
+        // TileJSON has no `{s}` placeholder, so list one template per subdomain instead
+        let tiles = if style.subdomains.is_empty() {
+            vec![format!("{base_url}/{}/a/{{z}}/{{x}}/{{y}}.png", style.name)]
+        } else {
+            style
+                .subdomains
+                .iter()
+                .map(|s| format!("{base_url}/{}/{s}/{{z}}/{{x}}/{{y}}.png", style.name))
+                .collect()
+        };
+
+        TileJson {
+            tilejson: "3.0.0",
+            id: style.name.clone(),
+            name: style.title.clone().unwrap_or_else(|| style.name.clone()),
+            attribution: style.attribution.clone(),
+            scheme: "xyz",
+            tiles,
+            minzoom: style.minzoom,
+            maxzoom: style.maxzoom,
+            bounds: style.bounds,
+        }
+    }
+}
+
+/// Base URL of this server as seen by the client
+pub fn base_url(config: &Config, host: &str) -> String {
+    match config.public_url {
+        Some(ref url) => url.trim_end_matches('/').to_string(),
+        None => format!("http://{host}"),
+    }
+}
+
+pub async fn style_tilejson(
+    Path(style): Path<String>,
+    Host(host): Host,
+    State(state): State<AppState>,
+) -> Response {
+    match state.config.style(&style) {
+        Some(style) => Json(TileJson::new(style, &base_url(&state.config, &host))).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown style: {style}")).into_response(),
+    }
+}
+
+pub async fn styles_index(Host(host): Host, State(state): State<AppState>) -> Json<Vec<TileJson>> {
+    let base_url = base_url(&state.config, &host);
+    Json(
+        state
+            .config
+            .styles
+            .iter()
+            .map(|style| TileJson::new(style, &base_url))
+            .collect(),
+    )
+}
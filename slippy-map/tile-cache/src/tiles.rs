@@ -0,0 +1,371 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    config::StyleConfig, etag, precache::precache_adjacent_tiles, render, snapshot, AppState,
+    TileFormat, TileId, MAX_SCALE, MAX_ZOOM,
+};
+
+/// Returns the tile's image, and whether it was already stored
+pub async fn get_tile(
+    state: &AppState,
+    style: &str,
+    idx: &str,
+    tile: TileId,
+) -> Result<(Vec<u8>, bool), String> {
+    if snapshot::parse(style).is_some() {
+        return snapshot::get_snapshot_tile(state, style, tile)
+            .await
+            .map(|data| (data, true));
+    }
+    if tile.format == TileFormat::Png {
+        return get_png_tile(state, style, idx, tile).await;
+    }
+    let Some(style_config) = state.config.style(style) else {
+        return Err(format!("Unknown style: {style}"));
+    };
+
+    if let Some(contents) = load_tile(state, style, tile).await? {
+        tracing::info!("Tile {style}/{tile} already stored as {:?}", tile.format);
+        return Ok((contents, true));
+    }
+
+    // Converted from the PNG, which is likely stored already.
+    // It is taken as downloaded, so that highlighting fresh tiles never ends up stored.
+    let (png_data, _) = load_png_tile(state, style, idx, tile.with_format(TileFormat::Png)).await?;
+    let data = convert_tile(style_config, tile, png_data).await?;
+    store_tile(state, style, tile, &data).await?;
+
+    Ok((data, false))
+}
+
+/// Encode the PNG tile in the tile's format
+async fn convert_tile(
+    style_config: &StyleConfig,
+    tile: TileId,
+    png_data: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let style = &style_config.name;
+    tracing::info!("Converting tile {style}/{tile} to {:?}", tile.format);
+    let style_config = style_config.clone();
+    tokio::task::spawn_blocking(move || tile.format.convert(&png_data, &style_config))
+        .await
+        .map_err(|why| format!("Tile conversion task failed\n{why}"))?
+        .map_err(|why| format!("Could not convert tile {style}/{tile}\n{why}"))
+}
+
+async fn get_png_tile(
+    state: &AppState,
+    style: &str,
+    idx: &str,
+    tile: TileId,
+) -> Result<(Vec<u8>, bool), String> {
+    let (data, was_stored) = load_png_tile(state, style, idx, tile).await?;
+    if was_stored {
+        Ok((data, true))
+    } else {
+        Ok((render::mark_fresh(data), false))
+    }
+}
+
+/// Returns the stored PNG tile, or downloads or upscales it as it is stored
+async fn load_png_tile(
+    state: &AppState,
+    style: &str,
+    idx: &str,
+    tile: TileId,
+) -> Result<(Vec<u8>, bool), String> {
+    let Some(style_config) = state.config.style(style) else {
+        return Err(format!("Unknown style: {style}"));
+    };
+
+    if let Some(contents) = load_tile(state, style, tile).await? {
+        tracing::info!("Tile {style}/{tile} already stored");
+        return Ok((contents, true));
+    }
+
+    let data = if style_config.upscales(tile.scale) {
+        // Made from the regular tile, which is likely stored already
+        let base = tile.with_scale(1);
+        let base_data = match load_tile(state, style, base).await? {
+            Some(contents) => contents,
+            None => download_tile(state, style_config, idx, base).await?,
+        };
+        tracing::info!("Upscaling tile {style}/{base} to {tile}");
+        let data = render::resize_tile(&base_data, tile.size())
+            .map_err(|why| format!("Could not upscale tile {style}/{tile}\n{why}"))?;
+        store_tile(state, style, tile, &data).await?;
+        data
+    } else {
+        download_tile(state, style_config, idx, tile).await?
+    };
+
+    Ok((data, false))
+}
+
+async fn load_tile(state: &AppState, style: &str, tile: TileId) -> Result<Option<Vec<u8>>, String> {
+    state
+        .store
+        .get(style, tile)
+        .await
+        .map_err(|why| format!("Could not read tile {style}/{tile}\n{why}"))
+}
+
+async fn store_tile(
+    state: &AppState,
+    style: &str,
+    tile: TileId,
+    data: &[u8],
+) -> Result<(), String> {
+    state
+        .store
+        .put(style, tile, data)
+        .await
+        .map_err(|why| format!("Could not save tile {style}/{tile}\n{why}"))
+}
+
+/// Fetch the tile from the source and store it, resized if the source's tiles are of another size
+async fn download_tile(
+    state: &AppState,
+    style_config: &StyleConfig,
+    idx: &str,
+    tile: TileId,
+) -> Result<Vec<u8>, String> {
+    let style = &style_config.name;
+    tracing::info!("Downloading tile {style}/{tile}");
+    let mut data = state.source.fetch(style_config, idx, tile).await?;
+    match render::image_dimensions(&data) {
+        Some((width, height)) if (width, height) != (tile.size(), tile.size()) => {
+            tracing::info!("Resizing tile {style}/{tile} from {width}x{height}");
+            data = render::resize_tile(&data, tile.size())
+                .map_err(|why| format!("Could not resize tile {style}/{tile}\n{why}"))?;
+        }
+        _ => {}
+    }
+    store_tile(state, style, tile, &data).await?;
+    Ok(data)
+}
+
+/// Download the tile again even if it is stored, replacing the stored one.
+/// Its stored copies at other scales and in other formats are made again as well,
+/// so that none of them keeps showing the old tile.
+pub async fn refresh_tile(
+    state: &AppState,
+    style: &str,
+    idx: &str,
+    tile: TileId,
+) -> Result<Vec<u8>, String> {
+    let Some(style_config) = state.config.style(style) else {
+        return Err(format!("Unknown style: {style}"));
+    };
+    let tile = tile.with_scale(1).with_format(TileFormat::Png);
+    let base_data = download_tile(state, style_config, idx, tile).await?;
+
+    for scale in 1..=MAX_SCALE {
+        let scaled = tile.with_scale(scale);
+        let png_data = if scale == 1 {
+            base_data.clone()
+        } else if load_tile(state, style, scaled).await?.is_none() {
+            // Conversions are made from the PNG at the same scale, so there are none either
+            continue;
+        } else if style_config.upscales(scale) {
+            tracing::info!("Upscaling tile {style}/{tile} to {scaled}");
+            let data = render::resize_tile(&base_data, scaled.size())
+                .map_err(|why| format!("Could not upscale tile {style}/{scaled}\n{why}"))?;
+            store_tile(state, style, scaled, &data).await?;
+            data
+        } else {
+            download_tile(state, style_config, idx, scaled).await?
+        };
+
+        for format in [TileFormat::Webp, TileFormat::Jpeg] {
+            let converted = scaled.with_format(format);
+            if load_tile(state, style, converted).await?.is_some() {
+                let data = convert_tile(style_config, converted, png_data.clone()).await?;
+                store_tile(state, style, converted, &data).await?;
+            }
+        }
+    }
+    Ok(base_data)
+}
+
+/// Parse the last path component of a tile URL, like `123.png`, `123@2x.webp` or `123`.
+/// The format is `None` if there is no extension.
+fn parse_y_and_scale(y_png: &str) -> Result<(u32, u8, Option<TileFormat>), String> {
+    let (y, format) = match y_png.split_once('.') {
+        None => (y_png, None),
+        Some((y, extension)) => match TileFormat::from_extension(extension) {
+            Some(format) => (y, Some(format)),
+            None => {
+                return Err(format!(
+                    "Unknown tile format `{extension}`, must be one of: png, webp, jpg"
+                ))
+            }
+        },
+    };
+    let (y, scale) = match y.split_once('@') {
+        None => (y, 1),
+        Some((y, scale)) => {
+            let scale = scale
+                .strip_suffix('x')
+                .and_then(|scale| scale.parse().ok())
+                .filter(|scale| (1..=MAX_SCALE).contains(scale));
+            match scale {
+                Some(scale) => (y, scale),
+                None => return Err(format!("Scale must look like `@2x`, from 1 to {MAX_SCALE}")),
+            }
+        }
+    };
+    match y.parse() {
+        Ok(y) => Ok((y, scale, format)),
+        Err(why) => Err(format!(
+            "Error parsing last path component into number: {why}"
+        )),
+    }
+}
+
+pub async fn fetch_tile(
+    Path((style, idx, zoom, x, y)): Path<(String, String, u8, u32, String)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let (y, scale, format) = match parse_y_and_scale(&y) {
+        Ok(parsed) => parsed,
+        Err(why) => return (StatusCode::BAD_REQUEST, why).into_response(),
+    };
+    // Without an extension in the URL, the format depends on the `Accept` header
+    let is_negotiated = format.is_none();
+    let format = format.unwrap_or_else(|| TileFormat::negotiate(&headers));
+    if zoom > MAX_ZOOM {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Zoom must be at most {MAX_ZOOM}"),
+        )
+            .into_response();
+    }
+    let side = 1u32 << zoom;
+    if x >= side || y >= side {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Column and row must be below {side} at zoom {zoom}"),
+        )
+            .into_response();
+    }
+
+    let tile = TileId::new(zoom, x, y)
+        .with_scale(scale)
+        .with_format(format);
+    serve_tile(&state, &style, &idx, tile, &headers, is_negotiated).await
+}
+
+/// Same as [`fetch_tile`], for clients that count rows from the bottom of the map as in TMS
+pub async fn fetch_tms_tile(
+    Path((style, idx, zoom, x, y)): Path<(String, String, u8, u32, String)>,
+    state: State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    // The row is followed by the scale and extension, like `123@2x.png`
+    let (tms_y, rest) = y.split_at(y.find(|c: char| !c.is_ascii_digit()).unwrap_or(y.len()));
+    let side = 1u64.checked_shl(zoom as u32).unwrap_or(0);
+    let tms_y = match tms_y.parse::<u64>() {
+        Ok(tms_y) if tms_y < side => tms_y,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Row must be a number below {side} at zoom {zoom}"),
+            )
+                .into_response()
+        }
+    };
+    let y = format!("{}{rest}", side - 1 - tms_y);
+    fetch_tile(Path((style, idx, zoom, x, y)), state, headers).await
+}
+
+/// Respond with the tile, or an image of the error.
+/// `is_negotiated` tells that the format was picked from the `Accept` header.
+pub async fn serve_tile(
+    state: &AppState,
+    style: &str,
+    idx: &str,
+    tile: TileId,
+    headers: &HeaderMap,
+    is_negotiated: bool,
+) -> Response {
+    if snapshot::parse(style).is_some() && !snapshot::exists(state, style).await {
+        return (StatusCode::NOT_FOUND, format!("Unknown snapshot: {style}")).into_response();
+    }
+    match get_tile(state, style, idx, tile).await {
+        Ok((img, was_stored)) => {
+            // Only views of tiles that the nightly refresh can download again are counted
+            let is_refreshable = state
+                .config
+                .style(style)
+                .is_some_and(|style_config| tile.zoom <= style_config.maxzoom);
+            if state.config.hot_areas.enabled && is_refreshable {
+                state.access_counts.record(style, tile);
+            }
+            // Fresh tiles are recolored when highlighting them, so clients must not keep those
+            let is_cacheable = was_stored || cfg!(not(feature = "debug-highlight-fresh"));
+            let etag = is_cacheable.then(|| etag::content_etag(&img));
+            let mut resp = match etag {
+                Some(ref etag) if etag::is_not_modified(headers, etag) => {
+                    StatusCode::NOT_MODIFIED.into_response()
+                }
+                _ => {
+                    let mut resp = img.into_response();
+                    resp.headers_mut().insert(
+                        "Content-Type",
+                        HeaderValue::from_static(tile.format.content_type()),
+                    );
+                    resp
+                }
+            };
+            if is_negotiated {
+                resp.headers_mut()
+                    .insert("Vary", HeaderValue::from_static("Accept"));
+            }
+            if let Some(etag) = etag {
+                resp.headers_mut()
+                    .insert("ETag", HeaderValue::from_str(&etag).unwrap());
+                resp.headers_mut().insert(
+                    "Cache-Control",
+                    HeaderValue::from_static("max-age=604800, public, immutable"),
+                );
+            } else {
+                resp.headers_mut().insert(
+                    "Cache-Control",
+                    HeaderValue::from_static("no-cache, no-store"),
+                );
+            }
+            precache_adjacent_tiles(state, style, idx, tile);
+
+            resp
+        }
+        Err(why) => {
+            let mut resp = render::render_error_image(&why, tile.scale).into_response();
+            resp.headers_mut()
+                .insert("Content-Type", HeaderValue::from_static("image/png"));
+            // The problem may be temporary, so the error must not stick in the browser's cache
+            resp.headers_mut().insert(
+                "Cache-Control",
+                HeaderValue::from_static("no-cache, no-store"),
+            );
+            resp
+        }
+    }
+}
+
+/// Report on the stored tiles and the space saved by deduplication
+pub async fn store_stats(State(state): State<AppState>) -> Response {
+    match state.store.stats().await {
+        Ok(stats) => stats.to_string().into_response(),
+        Err(why) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Could not collect stats\n{why}"),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,470 @@
+//! OGC WMTS 1.0.0 access to the tiles, for desktop GIS tools.
+//!
+//! Every style is a layer in the `GoogleMapsCompatible` tile matrix set, which is the XYZ scheme
+//! in Web Mercator: the tile matrix is the zoom, the row is `y` and the column is `x`.
+//! Tiles are requested either with KVP at `/wmts?SERVICE=WMTS&REQUEST=GetTile&...`,
+//! or RESTful at `/wmts/<layer>/default/GoogleMapsCompatible/<zoom>/<row>/<col>.png`.
+
+use std::{collections::HashMap, fmt::Write};
+
+use axum::{
+    extract::{Host, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    config::{Config, StyleConfig},
+    static_map::tile_range,
+    tilejson::base_url,
+    tiles::serve_tile,
+    AppState, TileFormat, TileId,
+};
+
+const TILE_MATRIX_SET: &str = "GoogleMapsCompatible";
+/// Layers have no other style than this one
+const STYLE: &str = "default";
+/// Half the side of the Web Mercator square, in meters
+const HALF_WORLD: f64 = 20037508.3427892;
+/// Scale denominator at zoom 0 in the GoogleMapsCompatible well-known scale set
+const ZOOM_0_SCALE: f64 = 559082264.0287178;
+const FORMATS: [TileFormat; 3] = [TileFormat::Png, TileFormat::Webp, TileFormat::Jpeg];
+
+/// An OWS exception report, with the parameter at fault as the locator
+struct WmtsError {
+    status: StatusCode,
+    code: &'static str,
+    locator: &'static str,
+    text: String,
+}
+
+impl WmtsError {
+    fn missing(param: &'static str) -> Self {
+        WmtsError {
+            status: StatusCode::BAD_REQUEST,
+            code: "MissingParameterValue",
+            locator: param,
+            text: format!("Missing parameter {param}"),
+        }
+    }
+
+    fn invalid(param: &'static str, text: String) -> Self {
+        WmtsError {
+            status: StatusCode::BAD_REQUEST,
+            code: "InvalidParameterValue",
+            locator: param,
+            text,
+        }
+    }
+
+    fn out_of_range(param: &'static str, text: String) -> Self {
+        WmtsError {
+            status: StatusCode::BAD_REQUEST,
+            code: "TileOutOfRange",
+            locator: param,
+            text,
+        }
+    }
+}
+
+impl IntoResponse for WmtsError {
+    fn into_response(self) -> Response {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<ows:ExceptionReport xmlns:ows="http://www.opengis.net/ows/1.1" version="1.1.0" xml:lang="en">
+  <ows:Exception exceptionCode="{}" locator="{}">
+    <ows:ExceptionText>{}</ows:ExceptionText>
+  </ows:Exception>
+</ows:ExceptionReport>
+"#,
+            self.code,
+            self.locator,
+            escape(&self.text)
+        );
+        xml_response(self.status, body)
+    }
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    let mut resp = (status, body).into_response();
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/xml"),
+    );
+    resp
+}
+
+/// Escape text to put it into XML elements and attributes
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// KVP requests at `/wmts`, for both `GetCapabilities` and `GetTile`
+pub async fn wmts_kvp(
+    Query(params): Query<HashMap<String, String>>,
+    Host(host): Host,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    // Parameter names are case-insensitive, their values are not
+    let params: HashMap<String, String> = params
+        .into_iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .collect();
+    let param = |name: &'static str| {
+        params
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+            .ok_or_else(|| WmtsError::missing(name))
+    };
+
+    let result = async {
+        let service = param("SERVICE")?;
+        if service != "WMTS" {
+            return Err(WmtsError::invalid(
+                "SERVICE",
+                format!("Unknown service {service}, only WMTS is supported"),
+            ));
+        }
+        match param("REQUEST")? {
+            "GetCapabilities" => Ok(capabilities_response(&state.config, &host)),
+            "GetTile" => {
+                let format = param("FORMAT")?;
+                let Some(format) = TileFormat::from_content_type(format) else {
+                    return Err(WmtsError::invalid(
+                        "FORMAT",
+                        format!("Unknown format {format}"),
+                    ));
+                };
+                let request = TileRequest {
+                    layer: param("LAYER")?,
+                    style: param("STYLE")?,
+                    tile_matrix_set: param("TILEMATRIXSET")?,
+                    tile_matrix: param("TILEMATRIX")?,
+                    tile_row: param("TILEROW")?,
+                    tile_col: param("TILECOL")?,
+                    format,
+                };
+                request.serve(&state, &headers).await
+            }
+            request => Err(WmtsError {
+                status: StatusCode::NOT_IMPLEMENTED,
+                code: "OperationNotSupported",
+                locator: "REQUEST",
+                text: format!("Unknown request {request}, must be GetCapabilities or GetTile"),
+            }),
+        }
+    };
+    result.await.unwrap_or_else(IntoResponse::into_response)
+}
+
+/// The RESTful `GetCapabilities`, at `/wmts/1.0.0/WMTSCapabilities.xml`
+pub async fn wmts_capabilities(Host(host): Host, State(state): State<AppState>) -> Response {
+    capabilities_response(&state.config, &host)
+}
+
+/// The RESTful `GetTile`, at `/wmts/:layer/:style/:tile_matrix_set/:tile_matrix/:tile_row/:tile_col`
+/// where the column has the extension of the format
+pub async fn wmts_tile(
+    Path((layer, style, tile_matrix_set, tile_matrix, tile_row, tile_col)): Path<(
+        String,
+        String,
+        String,
+        String,
+        String,
+        String,
+    )>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let Some((tile_col, format)) = tile_col
+        .split_once('.')
+        .and_then(|(col, extension)| Some((col, TileFormat::from_extension(extension)?)))
+    else {
+        return WmtsError::invalid(
+            "Format",
+            "The tile must end with an extension: png, webp or jpg".to_string(),
+        )
+        .into_response();
+    };
+    let request = TileRequest {
+        layer: &layer,
+        style: &style,
+        tile_matrix_set: &tile_matrix_set,
+        tile_matrix: &tile_matrix,
+        tile_row: &tile_row,
+        tile_col,
+        format,
+    };
+    request
+        .serve(&state, &headers)
+        .await
+        .unwrap_or_else(IntoResponse::into_response)
+}
+
+/// Parameters of `GetTile`, as given in either encoding
+struct TileRequest<'a> {
+    layer: &'a str,
+    style: &'a str,
+    tile_matrix_set: &'a str,
+    tile_matrix: &'a str,
+    tile_row: &'a str,
+    tile_col: &'a str,
+    format: TileFormat,
+}
+
+impl TileRequest<'_> {
+    async fn serve(&self, state: &AppState, headers: &HeaderMap) -> Result<Response, WmtsError> {
+        let tile = self.tile(state)?;
+        // Spread over the subdomains by position, as XYZ clients do by picking one per tile
+        let idx = state
+            .config
+            .style(self.layer)
+            .and_then(|style| {
+                let count = style.subdomains.len().max(1);
+                style
+                    .subdomains
+                    .get((tile.x as usize + tile.y as usize) % count)
+            })
+            .map_or("a", String::as_str);
+        Ok(serve_tile(state, self.layer, idx, tile, headers, false).await)
+    }
+
+    /// Check the parameters against the capabilities, and find the tile they refer to
+    fn tile(&self, state: &AppState) -> Result<TileId, WmtsError> {
+        let Some(style) = state.config.style(self.layer) else {
+            return Err(WmtsError::invalid(
+                "Layer",
+                format!("Unknown layer {}", self.layer),
+            ));
+        };
+        // Some clients leave the style empty for the default one
+        if !self.style.is_empty() && self.style != STYLE {
+            return Err(WmtsError::invalid(
+                "Style",
+                format!("Unknown style {}, must be {STYLE}", self.style),
+            ));
+        }
+        if self.tile_matrix_set != TILE_MATRIX_SET {
+            return Err(WmtsError::invalid(
+                "TileMatrixSet",
+                format!(
+                    "Unknown tile matrix set {}, must be {TILE_MATRIX_SET}",
+                    self.tile_matrix_set
+                ),
+            ));
+        }
+
+        let Ok(zoom) = self.tile_matrix.parse::<u8>() else {
+            return Err(WmtsError::invalid(
+                "TileMatrix",
+                format!("Unknown tile matrix {}", self.tile_matrix),
+            ));
+        };
+        if zoom < style.minzoom || zoom > style.maxzoom {
+            return Err(WmtsError::out_of_range(
+                "TileMatrix",
+                format!(
+                    "Tile matrix must be from {} to {}",
+                    style.minzoom, style.maxzoom
+                ),
+            ));
+        }
+        let side = 1u64 << zoom;
+        let (row, col) = match (self.tile_row.parse::<u64>(), self.tile_col.parse::<u64>()) {
+            (Ok(row), Ok(col)) => (row, col),
+            (Err(_), _) => {
+                return Err(WmtsError::invalid(
+                    "TileRow",
+                    format!("Tile row must be a number, not {}", self.tile_row),
+                ))
+            }
+            (_, Err(_)) => {
+                return Err(WmtsError::invalid(
+                    "TileCol",
+                    format!("Tile column must be a number, not {}", self.tile_col),
+                ))
+            }
+        };
+        if row >= side {
+            return Err(WmtsError::out_of_range(
+                "TileRow",
+                format!("Tile row must be below {side} in tile matrix {zoom}"),
+            ));
+        }
+        if col >= side {
+            return Err(WmtsError::out_of_range(
+                "TileCol",
+                format!("Tile column must be below {side} in tile matrix {zoom}"),
+            ));
+        }
+
+        Ok(TileId::new(zoom, col as u32, row as u32).with_format(self.format))
+    }
+}
+
+fn capabilities_response(config: &Config, host: &str) -> Response {
+    xml_response(
+        StatusCode::OK,
+        capabilities(config, &base_url(config, host)),
+    )
+}
+
+/// The `GetCapabilities` document, listing all styles as layers
+fn capabilities(config: &Config, base_url: &str) -> String {
+    let base_url = escape(base_url);
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Capabilities xmlns="http://www.opengis.net/wmts/1.0" xmlns:ows="http://www.opengis.net/ows/1.1" xmlns:xlink="http://www.w3.org/1999/xlink" version="1.0.0">
+  <ows:ServiceIdentification>
+    <ows:Title>Slippy map tile server</ows:Title>
+    <ows:ServiceType>OGC WMTS</ows:ServiceType>
+    <ows:ServiceTypeVersion>1.0.0</ows:ServiceTypeVersion>
+  </ows:ServiceIdentification>
+  <ows:OperationsMetadata>
+{}{}  </ows:OperationsMetadata>
+  <Contents>
+"#,
+        operation("GetCapabilities", &base_url),
+        operation("GetTile", &base_url),
+    );
+
+    for style in &config.styles {
+        layer(&mut xml, style, &base_url);
+    }
+
+    let maxzoom = config.styles.iter().map(|s| s.maxzoom).max().unwrap_or(0);
+    writeln!(
+        xml,
+        r#"    <TileMatrixSet>
+      <ows:Identifier>{TILE_MATRIX_SET}</ows:Identifier>
+      <ows:BoundingBox crs="urn:ogc:def:crs:EPSG::3857">
+        <ows:LowerCorner>{} {}</ows:LowerCorner>
+        <ows:UpperCorner>{HALF_WORLD} {HALF_WORLD}</ows:UpperCorner>
+      </ows:BoundingBox>
+      <ows:SupportedCRS>urn:ogc:def:crs:EPSG::3857</ows:SupportedCRS>
+      <WellKnownScaleSet>urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible</WellKnownScaleSet>"#,
+        -HALF_WORLD, -HALF_WORLD,
+    )
+    .unwrap();
+    for zoom in 0..=maxzoom {
+        let side = 1u64 << zoom;
+        writeln!(
+            xml,
+            r#"      <TileMatrix>
+        <ows:Identifier>{zoom}</ows:Identifier>
+        <ScaleDenominator>{}</ScaleDenominator>
+        <TopLeftCorner>{} {HALF_WORLD}</TopLeftCorner>
+        <TileWidth>256</TileWidth>
+        <TileHeight>256</TileHeight>
+        <MatrixWidth>{side}</MatrixWidth>
+        <MatrixHeight>{side}</MatrixHeight>
+      </TileMatrix>"#,
+            ZOOM_0_SCALE / side as f64,
+            -HALF_WORLD,
+        )
+        .unwrap();
+    }
+    writeln!(
+        xml,
+        r#"    </TileMatrixSet>
+  </Contents>
+  <ServiceMetadataURL xlink:href="{base_url}/wmts/1.0.0/WMTSCapabilities.xml"/>
+</Capabilities>"#
+    )
+    .unwrap();
+
+    xml
+}
+
+/// An operation available with KVP at `/wmts`, and RESTful at the resource URLs
+fn operation(name: &str, base_url: &str) -> String {
+    let rest_url = match name {
+        "GetCapabilities" => format!("{base_url}/wmts/1.0.0/WMTSCapabilities.xml"),
+        _ => format!("{base_url}/wmts/"),
+    };
+    format!(
+        r#"    <ows:Operation name="{name}">
+      <ows:DCP>
+        <ows:HTTP>
+          <ows:Get xlink:href="{base_url}/wmts?">
+            <ows:Constraint name="GetEncoding">
+              <ows:AllowedValues><ows:Value>KVP</ows:Value></ows:AllowedValues>
+            </ows:Constraint>
+          </ows:Get>
+          <ows:Get xlink:href="{rest_url}">
+            <ows:Constraint name="GetEncoding">
+              <ows:AllowedValues><ows:Value>RESTful</ows:Value></ows:AllowedValues>
+            </ows:Constraint>
+          </ows:Get>
+        </ows:HTTP>
+      </ows:DCP>
+    </ows:Operation>
+"#
+    )
+}
+
+fn layer(xml: &mut String, style: &StyleConfig, base_url: &str) {
+    let name = escape(&style.name);
+    let title = escape(style.title.as_deref().unwrap_or(&style.name));
+    let [west, south, east, north] = style.bounds;
+    writeln!(
+        xml,
+        r#"    <Layer>
+      <ows:Title>{title}</ows:Title>
+      <ows:Abstract>{}</ows:Abstract>
+      <ows:WGS84BoundingBox>
+        <ows:LowerCorner>{west} {south}</ows:LowerCorner>
+        <ows:UpperCorner>{east} {north}</ows:UpperCorner>
+      </ows:WGS84BoundingBox>
+      <ows:Identifier>{name}</ows:Identifier>
+      <Style isDefault="true">
+        <ows:Identifier>{STYLE}</ows:Identifier>
+      </Style>"#,
+        escape(&style.attribution),
+    )
+    .unwrap();
+    for format in FORMATS {
+        writeln!(xml, "      <Format>{}</Format>", format.content_type()).unwrap();
+    }
+
+    writeln!(
+        xml,
+        "      <TileMatrixSetLink>\n        <TileMatrixSet>{TILE_MATRIX_SET}</TileMatrixSet>\n        <TileMatrixSetLimits>"
+    )
+    .unwrap();
+    for zoom in style.minzoom..=style.maxzoom {
+        let ((min_col, min_row), (max_col, max_row)) = tile_range(style.bounds, zoom);
+        writeln!(
+            xml,
+            r#"          <TileMatrixLimits>
+            <TileMatrix>{zoom}</TileMatrix>
+            <MinTileRow>{min_row}</MinTileRow>
+            <MaxTileRow>{max_row}</MaxTileRow>
+            <MinTileCol>{min_col}</MinTileCol>
+            <MaxTileCol>{max_col}</MaxTileCol>
+          </TileMatrixLimits>"#
+        )
+        .unwrap();
+    }
+    writeln!(
+        xml,
+        "        </TileMatrixSetLimits>\n      </TileMatrixSetLink>"
+    )
+    .unwrap();
+
+    for format in FORMATS {
+        writeln!(
+            xml,
+            r#"      <ResourceURL format="{}" resourceType="tile" template="{base_url}/wmts/{name}/{{Style}}/{{TileMatrixSet}}/{{TileMatrix}}/{{TileRow}}/{{TileCol}}.{}"/>"#,
+            format.content_type(),
+            format.extension(),
+        )
+        .unwrap();
+    }
+    writeln!(xml, "    </Layer>").unwrap();
+}
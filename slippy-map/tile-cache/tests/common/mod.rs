@@ -0,0 +1,300 @@
+//! Harness for running tile-cache against a local stand-in for the tile providers,
+//! so that the tests never touch the network.
+
+use std::{
+    io::Cursor,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{self, Query},
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use image::{ImageOutputFormat, RgbImage};
+
+/// Mock of the OpenStreetMap, Thunderforest and Jawg tile servers.
+///
+/// Serves `/osm/{z}/{x}/{y}.png`, `/thunderforest/{s}/transport-dark/{z}/{x}/{y}.png?apikey=..`
+/// and `/jawg/{s}/jawg-matrix/{z}/{x}/{y}.png?access-token=..`.
+/// Anything under `/broken/` fails with a server error.
+pub struct MockUpstream {
+    pub base_url: String,
+    hits: Arc<Mutex<Vec<String>>>,
+}
+
+#[derive(serde::Deserialize)]
+struct KeyQuery {
+    apikey: Option<String>,
+    #[serde(rename = "access-token")]
+    access_token: Option<String>,
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        let hits = Arc::new(Mutex::new(vec![]));
+
+        let record = |hits: &Arc<Mutex<Vec<String>>>, uri: &Uri| {
+            hits.lock().unwrap().push(uri.path().to_string());
+        };
+
+        let app = Router::new()
+            .route(
+                "/osm/:z/:x/:y_png",
+                get({
+                    let hits = hits.clone();
+                    move |uri: Uri, extract::Path((z, x, y)): extract::Path<(u8, u32, String)>| async move {
+                        record(&hits, &uri);
+                        tile_response(z, x, &y)
+                    }
+                }),
+            )
+            .route(
+                "/thunderforest/:s/transport-dark/:z/:x/:y_png",
+                get({
+                    let hits = hits.clone();
+                    move |uri: Uri,
+                          Query(key): Query<KeyQuery>,
+                          extract::Path((_s, z, x, y)): extract::Path<(String, u8, u32, String)>| async move {
+                        record(&hits, &uri);
+                        match key.apikey.as_deref() {
+                            Some("test-key") => tile_response(z, x, &y),
+                            _ => StatusCode::FORBIDDEN.into_response(),
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/jawg/:s/jawg-matrix/:z/:x/:y_png",
+                get({
+                    let hits = hits.clone();
+                    move |uri: Uri,
+                          Query(key): Query<KeyQuery>,
+                          extract::Path((_s, z, x, y)): extract::Path<(String, u8, u32, String)>| async move {
+                        record(&hits, &uri);
+                        match key.access_token.as_deref() {
+                            Some("test-token") => tile_response(z, x, &y),
+                            _ => StatusCode::FORBIDDEN.into_response(),
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/broken/*rest",
+                get({
+                    let hits = hits.clone();
+                    move |uri: Uri| async move {
+                        record(&hits, &uri);
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        MockUpstream {
+            base_url: format!("http://{addr}"),
+            hits,
+        }
+    }
+
+    /// Paths requested so far
+    pub fn hits(&self) -> Vec<String> {
+        self.hits.lock().unwrap().clone()
+    }
+
+    // Only the online tests see upstream requests
+    #[cfg(feature = "online")]
+    pub fn was_hit(&self, path: &str) -> bool {
+        self.hits().iter().any(|p| p == path)
+    }
+
+    /// Styles as in the default config, but pointing at this mock
+    pub fn styles_config(&self) -> String {
+        let base = &self.base_url;
+        format!(
+            r#"
+            [[styles]]
+            name = "_"
+            url = "{base}/osm/{{z}}/{{x}}/{{y}}.png"
+
+            [[styles]]
+            name = "transportdark"
+            url = "{base}/thunderforest/{{s}}/transport-dark/{{z}}/{{x}}/{{y}}.png?apikey=test-key"
+            retina_url = "{base}/thunderforest/{{s}}/transport-dark/{{z}}/{{x}}/{{y}}@2x.png?apikey=test-key"
+
+            [[styles]]
+            name = "matrix"
+            url = "{base}/jawg/{{s}}/jawg-matrix/{{z}}/{{x}}/{{y}}.png?access-token=test-token"
+
+            [[styles]]
+            name = "broken"
+            url = "{base}/broken/{{z}}/{{x}}/{{y}}.png"
+            "#
+        )
+    }
+}
+
+/// The image that the mock serves for a tile, distinct for every tile
+#[cfg(feature = "online")]
+pub fn tile_png(z: u8, x: u32, y: u32) -> Vec<u8> {
+    tile_png_sized(z, x, y, 256)
+}
+
+pub fn tile_png_sized(z: u8, x: u32, y: u32, size: u32) -> Vec<u8> {
+    let image = RgbImage::from_pixel(
+        size,
+        size,
+        image::Rgb([z.wrapping_mul(10), x as u8, y as u8]),
+    );
+    let mut output = vec![];
+    image
+        .write_to(&mut Cursor::new(&mut output), ImageOutputFormat::Png)
+        .unwrap();
+    output
+}
+
+/// Serves `<y>.png` as 256px, and `<y>@2x.png` as 512px
+fn tile_response(z: u8, x: u32, y_png: &str) -> Response {
+    let y = y_png.trim_end_matches(".png");
+    let (y, size) = match y.strip_suffix("@2x") {
+        Some(y) => (y, 512),
+        None => (y, 256),
+    };
+    match y.parse() {
+        Ok(y) => (
+            [("Content-Type", "image/png")],
+            tile_png_sized(z, x, y, size),
+        )
+            .into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// A running tile-cache process with its own config and cache directory
+pub struct TileCache {
+    pub base_url: String,
+    pub cache_dir: PathBuf,
+    _dir: tempfile::TempDir,
+    process: Child,
+}
+
+impl TileCache {
+    pub async fn start(upstream: &MockUpstream) -> Self {
+        Self::start_with_config(&upstream.styles_config()).await
+    }
+
+    pub async fn start_with_config(extra_config: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        std::fs::create_dir_all(&cache_dir).unwrap();
+
+        // The port may get taken between picking it and the server binding it,
+        // in which case the server exits and is started again on another port.
+        for _ in 0..5 {
+            let addr = free_addr();
+            let config_path = dir.path().join("tile-cache.toml");
+            std::fs::write(
+                &config_path,
+                format!(
+                    "listen = \"{addr}\"\ncache_dir = \"{}\"\n{extra_config}\n[admin]\ntoken = \"{ADMIN_TOKEN}\"\n",
+                    cache_dir.display()
+                ),
+            )
+            .unwrap();
+
+            let mut process = Command::new(env!("CARGO_BIN_EXE_tile-cache"))
+                .env("TILE_CACHE_CONFIG", &config_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+
+            let base_url = format!("http://{addr}");
+            if wait_until_ready(&mut process, &base_url).await {
+                return TileCache {
+                    base_url,
+                    cache_dir,
+                    _dir: dir,
+                    process,
+                };
+            }
+        }
+        panic!("Could not start tile-cache");
+    }
+
+    pub async fn get(&self, path: &str) -> reqwest::Response {
+        reqwest::get(format!("{}{path}", self.base_url))
+            .await
+            .unwrap()
+    }
+
+    /// Request an admin route with the token from the config
+    pub async fn admin_get(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin{path}", self.base_url))
+            .bearer_auth(ADMIN_TOKEN)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Put a tile into the cache directory, as if it was downloaded before
+    pub fn seed(&self, style: &str, z: u8, x: u32, y: u32, data: &[u8]) {
+        let dir = self.cache_dir.join(format!("{style}/{z}/{x}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{y}.png")), data).unwrap();
+    }
+
+    pub fn cached_tile(&self, style: &str, z: u8, x: u32, y: u32) -> Option<Vec<u8>> {
+        std::fs::read(tile_path(&self.cache_dir, style, z, x, y)).ok()
+    }
+}
+
+impl Drop for TileCache {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Wait until the server answers, or return false if it has exited
+async fn wait_until_ready(process: &mut Child, base_url: &str) -> bool {
+    for _ in 0..200 {
+        if let Ok(Some(_)) = process.try_wait() {
+            return false;
+        }
+        if let Ok(resp) = reqwest::get(base_url).await {
+            if resp.text().await.ok().as_deref() == Some("Slippy map tile server!") {
+                return true;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("tile-cache did not start listening on {base_url}");
+}
+
+fn tile_path(cache_dir: &Path, style: &str, z: u8, x: u32, y: u32) -> PathBuf {
+    cache_dir.join(format!("{style}/{z}/{x}/{y}.png"))
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
@@ -0,0 +1,1451 @@
+//! In-process tests of the router, with the store and tile source swapped for fakes.
+
+use std::{
+    io::Cursor,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use image::{ImageOutputFormat, RgbImage};
+use tile_cache::{
+    config::StyleConfig,
+    source::{OfflineSource, SynthesizedSource},
+    store::MemoryStore,
+    Config, RouterBuilder, TileFormat, TileId, TileSource, TileStore,
+};
+use tower::ServiceExt;
+
+/// Config with a single `test` style
+fn test_config(extra_config: &str) -> Config {
+    test_config_with_style(extra_config, "")
+}
+
+fn test_config_with_style(extra_config: &str, style_config: &str) -> Config {
+    toml::from_str(&format!(
+        r#"
+        {extra_config}
+
+        [[styles]]
+        name = "test"
+        url = "http://127.0.0.1:9/{{z}}/{{x}}/{{y}}.png"
+        {style_config}
+        "#
+    ))
+    .unwrap()
+}
+
+/// Router over a store that already has the tile `test/1/0/1`
+async fn test_app(extra_config: &str) -> Router {
+    let store = MemoryStore::new();
+    store
+        .put("test", TileId::new(1, 0, 1), b"not really a png")
+        .await
+        .unwrap();
+    RouterBuilder::new(test_config(extra_config))
+        .store(store)
+        .source(OfflineSource)
+        .build()
+}
+
+/// A plain tile image, since fetched tiles are decoded when highlighting fresh ones
+fn png_tile(shade: u8) -> Vec<u8> {
+    let image = RgbImage::from_pixel(256, 256, image::Rgb([shade, shade, shade]));
+    let mut output = vec![];
+    image
+        .write_to(&mut Cursor::new(&mut output), ImageOutputFormat::Png)
+        .unwrap();
+    output
+}
+
+/// What the fake sources serve for every tile
+fn fetched_png() -> Vec<u8> {
+    png_tile(200)
+}
+
+fn get_tile(if_none_match: Option<&str>, origin: Option<&str>) -> Request<Body> {
+    let mut req = Request::builder().uri("/test/a/1/0/1.png");
+    if let Some(etag) = if_none_match {
+        req = req.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(origin) = origin {
+        req = req.header(header::ORIGIN, origin);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+/// Returns the same image for every tile, counting how many were asked for
+#[derive(Clone, Default)]
+struct CountingSource {
+    fetches: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl TileSource for CountingSource {
+    async fn fetch(
+        &self,
+        _style: &StyleConfig,
+        _idx: &str,
+        _tile: TileId,
+    ) -> Result<Vec<u8>, String> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        Ok(fetched_png())
+    }
+
+    // Keeps precaching of adjacent tiles out of the counts
+    fn is_online(&self) -> bool {
+        false
+    }
+}
+
+#[tokio::test]
+async fn stored_tile_has_etag() {
+    let resp = test_app("")
+        .await
+        .oneshot(get_tile(None, None))
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()[header::ETAG].to_str().unwrap();
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+    assert_eq!(
+        resp.headers()[header::CACHE_CONTROL],
+        "max-age=604800, public, immutable"
+    );
+}
+
+#[tokio::test]
+async fn matching_etag_gives_not_modified() {
+    let resp = test_app("")
+        .await
+        .oneshot(get_tile(None, None))
+        .await
+        .unwrap();
+    let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+
+    let resp = test_app("")
+        .await
+        .oneshot(get_tile(Some(&etag), None))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()[header::ETAG], etag.as_str());
+
+    let resp = test_app("")
+        .await
+        .oneshot(get_tile(Some(&format!("\"other\", W/{etag}")), None))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let resp = test_app("")
+        .await
+        .oneshot(get_tile(Some("\"other\""), None))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn missing_tile_has_no_etag() {
+    let app = RouterBuilder::new(test_config(""))
+        .store(MemoryStore::new())
+        .source(OfflineSource)
+        .build();
+    let resp = app.oneshot(get_tile(Some("*"), None)).await.unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get(header::ETAG).is_none());
+    assert_eq!(resp.headers()[header::CACHE_CONTROL], "no-cache, no-store");
+}
+
+#[tokio::test]
+async fn cors_allows_any_origin_by_default() {
+    let resp = test_app("")
+        .await
+        .oneshot(get_tile(None, Some("http://example.com")))
+        .await
+        .unwrap();
+
+    assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    assert_eq!(
+        resp.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+        "etag"
+    );
+}
+
+#[tokio::test]
+async fn cors_respects_configured_origins() {
+    let config = r#"cors_allow_origins = ["http://allowed.example"]"#;
+
+    let resp = test_app(config)
+        .await
+        .oneshot(get_tile(None, Some("http://allowed.example")))
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "http://allowed.example"
+    );
+
+    let resp = test_app(config)
+        .await
+        .oneshot(get_tile(None, Some("http://other.example")))
+        .await
+        .unwrap();
+    assert!(resp
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+}
+
+#[tokio::test]
+async fn cors_can_be_disabled() {
+    let resp = test_app("cors_allow_origins = []")
+        .await
+        .oneshot(get_tile(None, Some("http://example.com")))
+        .await
+        .unwrap();
+
+    assert!(resp
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+}
+
+async fn get_text(app: Router, path: &str) -> (StatusCode, String) {
+    let resp = app
+        .oneshot(
+            Request::get(path)
+                .header(header::HOST, "tiles.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+#[tokio::test]
+async fn tilejson_urls_follow_host_header() {
+    let (status, body) = get_text(test_app("").await, "/test/tiles.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(r#""id":"test""#));
+    // One template per subdomain, as TileJSON has no `{s}`
+    for s in ["a", "b", "c"] {
+        assert!(
+            body.contains(&format!(
+                r#""http://tiles.example.com/test/{s}/{{z}}/{{x}}/{{y}}.png""#
+            )),
+            "{body}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn tilejson_urls_prefer_public_url() {
+    let app = RouterBuilder::new(test_config_with_style(
+        r#"public_url = "https://maps.example.org/tiles/""#,
+        r#"subdomains = ["x", "y"]"#,
+    ))
+    .store(MemoryStore::new())
+    .source(OfflineSource)
+    .build();
+    let (status, body) = get_text(app, "/styles.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.starts_with('['));
+    assert!(body.contains(r#""https://maps.example.org/tiles/test/x/{z}/{x}/{y}.png""#));
+    assert!(body.contains(r#""https://maps.example.org/tiles/test/y/{z}/{x}/{y}.png""#));
+    assert!(!body.contains("tiles.example.com"));
+    assert!(!body.contains("/test/a/"));
+}
+
+#[tokio::test]
+async fn tilejson_of_unknown_style_is_not_found() {
+    let (status, _) = get_text(test_app("").await, "/nope/tiles.json").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn fetched_tile_is_stored_and_reused() {
+    let store = Arc::new(MemoryStore::new());
+    let source = CountingSource::default();
+    let app = RouterBuilder::new(test_config(""))
+        .store(store.clone())
+        .source(source.clone())
+        .build();
+
+    for attempt in 0..3 {
+        let resp = app.clone().oneshot(get_tile(None, None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        // Only the fresh tile is recolored when highlighting those
+        if attempt > 0 || cfg!(not(feature = "debug-highlight-fresh")) {
+            assert_eq!(body.as_ref(), fetched_png());
+        }
+    }
+
+    assert_eq!(source.fetches.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        store.get("test", TileId::new(1, 0, 1)).await.unwrap(),
+        Some(fetched_png())
+    );
+}
+
+#[tokio::test]
+async fn synthesized_tiles_are_images() {
+    let app = RouterBuilder::new(test_config(""))
+        .store(MemoryStore::new())
+        .source(SynthesizedSource)
+        .build();
+
+    let resp = app.oneshot(get_tile(None, None)).await.unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (256, 256));
+}
+
+/// Status and body of a static map request, and how many tiles it fetched
+async fn static_map(query: &str) -> (StatusCode, Vec<u8>, usize) {
+    let source = CountingSource::default();
+    let app = RouterBuilder::new(test_config(""))
+        .store(MemoryStore::new())
+        .source(source.clone())
+        .build();
+    let resp = app
+        .oneshot(
+            Request::get(format!("/static/test?{query}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    (status, body.to_vec(), source.fetches.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn static_map_is_rendered() {
+    let (status, body, fetches) =
+        static_map("center=0,0&zoom=1&size=300x200&markers=0,0,hole;10,10&scalebar=true").await;
+    assert_eq!(status, StatusCode::OK);
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (300, 200));
+    // The four tiles of zoom 1 all show up in the middle of the world
+    assert_eq!(fetches, 4);
+}
+
+#[tokio::test]
+async fn static_map_rejects_bad_markers_before_fetching() {
+    for markers in [
+        "0,0,unicorn",
+        "north,0,hole",
+        "0",
+        "inf,0,hole",
+        "0,NaN",
+        "91,0",
+    ] {
+        let (status, _, fetches) =
+            static_map(&format!("center=0,0&zoom=1&markers={markers}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{markers}");
+        assert_eq!(fetches, 0, "{markers}");
+    }
+}
+
+#[tokio::test]
+async fn static_map_rejects_centers_off_the_map() {
+    for center in [
+        "inf,0", "0,-inf", "NaN,0", "0,NaN", "85.06,0", "-90,0", "0,180.1", "0,-1e300",
+    ] {
+        let (status, _, fetches) = static_map(&format!("center={center}&zoom=1")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{center}");
+        assert_eq!(fetches, 0, "{center}");
+    }
+}
+
+#[tokio::test]
+async fn static_map_rejects_bboxes_off_the_map() {
+    for bbox in [
+        "-inf,0,1,1",
+        "0,0,1,NaN",
+        "0,-85.06,1,1",
+        "0,0,1,1e300",
+        "-180.1,0,1,1",
+        "0,0,200,1",
+    ] {
+        let (status, _, fetches) = static_map(&format!("bbox={bbox}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{bbox}");
+        assert_eq!(fetches, 0, "{bbox}");
+    }
+}
+
+#[tokio::test]
+async fn static_map_accepts_the_edges_of_the_map() {
+    let (status, _, _) = static_map("center=85.05,180&zoom=1&size=64x64").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = static_map("bbox=-180,-85.05,180,85.05&size=64x64").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn static_map_rejects_bad_sizes() {
+    for size in ["4096x512", "512x2049", "0x10", "512"] {
+        let (status, _, fetches) = static_map(&format!("center=0,0&zoom=1&size={size}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{size}");
+        assert_eq!(fetches, 0, "{size}");
+    }
+}
+
+#[tokio::test]
+async fn retina_tile_is_upscaled_from_stored_one() {
+    let store = Arc::new(MemoryStore::new());
+    let app = RouterBuilder::new(test_config("precache_enabled = false"))
+        .store(store.clone())
+        .source(SynthesizedSource)
+        .build();
+    let tile = TileId::new(1, 0, 1);
+    app.clone()
+        .oneshot(
+            Request::get("/test/a/1/0/1.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    // The response may be recolored when highlighting fresh tiles, the stored tile is not
+    let regular = store.get("test", tile).await.unwrap().unwrap();
+
+    let resp = app
+        .oneshot(
+            Request::get("/test/a/1/0/1@2x.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (512, 512));
+
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.get("test", tile).await.unwrap(), Some(regular));
+    assert!(store
+        .get("test", tile.with_scale(2))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+fn vary_has_accept(resp: &axum::response::Response) -> bool {
+    resp.headers()
+        .get_all(header::VARY)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap().split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("accept"))
+}
+
+#[test]
+fn format_follows_accept_header() {
+    let negotiate = |accept: Option<&str>| {
+        let mut headers = axum::http::HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(header::ACCEPT, accept.parse().unwrap());
+        }
+        TileFormat::negotiate(&headers)
+    };
+
+    assert_eq!(negotiate(None), TileFormat::Png);
+    assert_eq!(negotiate(Some("image/png")), TileFormat::Png);
+    assert_eq!(negotiate(Some("text/html")), TileFormat::Png);
+    assert_eq!(
+        negotiate(Some(
+            "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8"
+        )),
+        TileFormat::Webp
+    );
+    assert_eq!(
+        negotiate(Some("image/webp;q=0.5,image/png;q=0.9")),
+        TileFormat::Png
+    );
+    assert_eq!(
+        negotiate(Some("image/jpeg, image/*;q=0.1")),
+        TileFormat::Jpeg
+    );
+}
+
+#[tokio::test]
+async fn tile_is_converted_to_requested_format() {
+    let store = Arc::new(MemoryStore::new());
+    let app = RouterBuilder::new(test_config("precache_enabled = false"))
+        .store(store.clone())
+        .source(SynthesizedSource)
+        .build();
+
+    // Also with CORS disabled, where no other layer sets `Vary`
+    let resp = RouterBuilder::new(test_config("cors_allow_origins = []"))
+        .store(MemoryStore::new())
+        .source(SynthesizedSource)
+        .build()
+        .oneshot(Request::get("/test/a/1/0/1").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+    assert!(vary_has_accept(&resp));
+
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::get("/test/a/1/0/1")
+                .header(header::ACCEPT, "image/webp,*/*")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/webp");
+    assert!(vary_has_accept(&resp));
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(
+        image::guess_format(&body).unwrap(),
+        image::ImageFormat::WebP
+    );
+
+    let resp = app
+        .oneshot(
+            Request::get("/test/a/1/0/1.jpg")
+                .header(header::ACCEPT, "image/webp,*/*")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/jpeg");
+
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(
+        image::guess_format(&body).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+
+    // Both conversions are made from the same PNG, and all three are stored
+    let tile = TileId::new(1, 0, 1);
+    assert_eq!(store.len(), 3);
+    for format in [TileFormat::Png, TileFormat::Webp, TileFormat::Jpeg] {
+        assert!(store
+            .get("test", tile.with_format(format))
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
+
+#[tokio::test]
+async fn stats_report_deduplication() {
+    let store = MemoryStore::new();
+    for x in 0..4 {
+        store
+            .put("test", TileId::new(2, x, 0), b"same")
+            .await
+            .unwrap();
+    }
+    let app = RouterBuilder::new(test_config("admin = { token = \"secret\" }"))
+        .store(store)
+        .source(OfflineSource)
+        .build();
+
+    let resp = app
+        .oneshot(
+            Request::get("/admin/stats")
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        "Tiles: 4, unique tiles: 1, total size: 16 bytes, stored size: 4 bytes, saved by deduplication: 12 bytes (75.0%)"
+    );
+}
+
+async fn admin_status(
+    extra_config: &str,
+    authorization: Option<&str>,
+) -> (StatusCode, Option<String>) {
+    let app = RouterBuilder::new(test_config(extra_config))
+        .store(MemoryStore::new())
+        .source(OfflineSource)
+        .build();
+    let mut req = Request::get("/admin/stats");
+    if let Some(authorization) = authorization {
+        req = req.header(header::AUTHORIZATION, authorization);
+    }
+    let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+    let challenge = resp
+        .headers()
+        .get(header::WWW_AUTHENTICATE)
+        .map(|value| value.to_str().unwrap().to_string());
+    (resp.status(), challenge)
+}
+
+#[tokio::test]
+async fn admin_routes_are_disabled_without_credentials() {
+    assert_eq!(admin_status("", None).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(
+        admin_status("", Some("Bearer anything")).await.0,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn admin_routes_accept_bearer_token() {
+    let config = r#"admin = { token = "secret" }"#;
+    assert_eq!(
+        admin_status(config, None).await,
+        (StatusCode::UNAUTHORIZED, Some("Bearer".to_string()))
+    );
+    assert_eq!(
+        admin_status(config, Some("Bearer wrong")).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        admin_status(config, Some("Bearer secret")).await.0,
+        StatusCode::OK
+    );
+    assert_eq!(
+        admin_status(config, Some("bearer secret")).await.0,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn admin_routes_accept_basic_auth() {
+    let config = r#"admin = { username = "admin", password = "hunter2" }"#;
+    assert_eq!(
+        admin_status(config, None).await,
+        (
+            StatusCode::UNAUTHORIZED,
+            Some(r#"Basic realm="tile-cache admin""#.to_string())
+        )
+    );
+    // admin:hunter2
+    assert_eq!(
+        admin_status(config, Some("Basic YWRtaW46aHVudGVyMg=="))
+            .await
+            .0,
+        StatusCode::OK
+    );
+    // admin:hunter3
+    assert_eq!(
+        admin_status(config, Some("Basic YWRtaW46aHVudGVyMw=="))
+            .await
+            .0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        admin_status(config, Some("Bearer hunter2")).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+fn from_client(path: &str, ip: [u8; 4]) -> Request<Body> {
+    let mut req = Request::get(path).body(Body::empty()).unwrap();
+    req.extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from((ip, 50000))));
+    req
+}
+
+#[tokio::test]
+async fn clients_are_rate_limited() {
+    let app = test_app(
+        r#"
+        admin = { token = "secret" }
+        rate_limit = { requests_per_second = 1, burst = 2 }
+        "#,
+    )
+    .await;
+
+    for _ in 0..2 {
+        let resp = app
+            .clone()
+            .oneshot(from_client("/test/a/1/0/1.png", [10, 0, 0, 1]))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let resp = app
+        .clone()
+        .oneshot(from_client("/test/a/1/0/1.png", [10, 0, 0, 1]))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers()[header::RETRY_AFTER], "1");
+
+    // Other clients have their own limit
+    let resp = app
+        .clone()
+        .oneshot(from_client("/test/a/1/0/1.png", [10, 0, 0, 2]))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Admin routes are not limited
+    let mut req = from_client("/admin/stats", [10, 0, 0, 1]);
+    req.headers_mut()
+        .insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    assert_eq!(app.oneshot(req).await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn forwarded_clients_are_told_apart_by_the_proxy_entry() {
+    let app = test_app(
+        r#"
+        rate_limit = { requests_per_second = 1, burst = 1, trust_forwarded_for = true }
+        "#,
+    )
+    .await;
+    let behind_proxy = |forwarded_for: &str| {
+        let mut req = from_client("/test/a/1/0/1.png", [10, 0, 0, 100]);
+        req.headers_mut()
+            .insert("X-Forwarded-For", forwarded_for.parse().unwrap());
+        req
+    };
+
+    let resp = app
+        .clone()
+        .oneshot(behind_proxy("1.1.1.1, 10.0.0.1"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // Made up entries in front of the proxy's do not make a new client
+    let resp = app
+        .clone()
+        .oneshot(behind_proxy("2.2.2.2, 10.0.0.1"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let resp = app.oneshot(behind_proxy("10.0.0.2")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+/// Records which tiles were fetched, as an online source that precaching is done for
+#[derive(Clone, Default)]
+struct RecordingSource {
+    fetched: Arc<std::sync::Mutex<Vec<TileId>>>,
+}
+
+#[async_trait]
+impl TileSource for RecordingSource {
+    async fn fetch(
+        &self,
+        _style: &StyleConfig,
+        _idx: &str,
+        tile: TileId,
+    ) -> Result<Vec<u8>, String> {
+        self.fetched.lock().unwrap().push(tile);
+        Ok(fetched_png())
+    }
+}
+
+/// Request `test/a/3/4/2.png` and return the other tiles fetched in the background
+async fn precached_tiles(extra_config: &str, style_config: &str) -> Vec<(u8, u32, u32)> {
+    precached_around("/test/a/3/4/2.png", extra_config, style_config).await
+}
+
+async fn precached_around(
+    path: &str,
+    extra_config: &str,
+    style_config: &str,
+) -> Vec<(u8, u32, u32)> {
+    let source = RecordingSource::default();
+    let app = RouterBuilder::new(test_config_with_style(extra_config, style_config))
+        .store(MemoryStore::new())
+        .source(source.clone())
+        .build();
+    // Tiles off the map are refused, so nothing may be precached around them either
+    app.oneshot(Request::get(path).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let requested = path.trim_start_matches("/test/a/").trim_end_matches(".png");
+
+    // Wait until the background fetches stop coming
+    let mut count = 0;
+    loop {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let new_count = source.fetched.lock().unwrap().len();
+        if new_count == count {
+            break;
+        }
+        count = new_count;
+    }
+
+    let mut tiles: Vec<_> = source
+        .fetched
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|tile| tile.to_string() != requested)
+        .map(|tile| (tile.zoom, tile.x, tile.y))
+        .collect();
+    tiles.sort();
+    tiles
+}
+
+#[tokio::test]
+async fn default_precache_is_ancestors_and_two_levels_down() {
+    let tiles = precached_tiles("", "").await;
+    assert_eq!(tiles.len(), 3 + 4 + 16);
+    assert!(tiles.contains(&(0, 0, 0)));
+    assert!(tiles.contains(&(1, 1, 0)));
+    assert!(tiles.contains(&(2, 2, 1)));
+    assert!(tiles.contains(&(4, 9, 5)));
+    assert!(tiles.contains(&(5, 19, 11)));
+}
+
+#[tokio::test]
+async fn tiles_off_the_map_are_not_precached_around() {
+    let everything = "precache = { levels_down = 4, neighbor_ring = 3 }";
+    for path in [
+        "/test/a/5/4000000000/0.png",
+        "/test/a/5/0/32.png",
+        "/test/a/25/0/0.png",
+    ] {
+        assert_eq!(precached_around(path, "", everything).await, [], "{path}");
+    }
+}
+
+#[tokio::test]
+async fn precache_policy_is_per_style() {
+    assert_eq!(
+        precached_tiles("", "precache = { levels_down = 0 }").await,
+        [(0, 0, 0), (1, 1, 0), (2, 2, 1)]
+    );
+    assert_eq!(
+        precached_tiles("", "precache = { ancestors = false, levels_down = 1 }").await,
+        [(4, 8, 4), (4, 8, 5), (4, 9, 4), (4, 9, 5)]
+    );
+    assert_eq!(
+        precached_tiles(
+            "",
+            "precache = { ancestors = false, levels_down = 0, neighbor_ring = 1 }"
+        )
+        .await,
+        [
+            (3, 3, 1),
+            (3, 3, 2),
+            (3, 3, 3),
+            (3, 4, 1),
+            (3, 4, 3),
+            (3, 5, 1),
+            (3, 5, 2),
+            (3, 5, 3)
+        ]
+    );
+    assert!(
+        precached_tiles("", "precache = { ancestors = false, levels_down = 0 }")
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn precache_can_be_disabled_globally() {
+    assert!(precached_tiles("precache_enabled = false", "")
+        .await
+        .is_empty());
+}
+
+/// Config counting views by tiles at zoom 2, with admin routes behind the token `secret`
+fn hot_areas_config(hot_areas_config: &str) -> Config {
+    test_config(&format!(
+        r#"
+        precache_enabled = false
+
+        [admin]
+        token = "secret"
+
+        [hot_areas]
+        area_zoom = 2
+        {hot_areas_config}
+        "#
+    ))
+}
+
+async fn view_tile(app: &Router, path: &str) {
+    let req = Request::get(path).body(Body::empty()).unwrap();
+    app.clone().oneshot(req).await.unwrap();
+}
+
+async fn admin_text(app: &Router, path: &str) -> String {
+    let req = Request::get(path)
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    let resp = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn hot_areas_count_views_by_area() {
+    let app = RouterBuilder::new(hot_areas_config("top_areas = 2"))
+        .store(MemoryStore::new())
+        .source(SynthesizedSource)
+        .build();
+    // Tiles above the area zoom do not tell where one looks
+    for path in [
+        "/test/a/3/4/2.png",
+        "/test/a/4/9/5.png",
+        "/test/a/3/0/0.png",
+        "/test/a/1/0/0.png",
+    ] {
+        view_tile(&app, path).await;
+    }
+    // Neither do tiles that are not served
+    for path in [
+        "/unknown/a/3/0/0.png",
+        "/test/a/3/8/0.png",
+        "/test/a/50/0/0.png",
+        "/test/a/40/4000000000/0.png",
+    ] {
+        view_tile(&app, path).await;
+    }
+
+    assert_eq!(
+        admin_text(&app, "/admin/hot-areas").await,
+        "test 2/2/1 2\ntest 2/0/0 1\n"
+    );
+}
+
+#[tokio::test]
+async fn hot_areas_count_the_deepest_tiles() {
+    let config = test_config_with_style(
+        "[admin]\ntoken = \"secret\"\n[hot_areas]\narea_zoom = 2",
+        "maxzoom = 60",
+    );
+    let app = RouterBuilder::new(config)
+        .store(MemoryStore::new())
+        .source(SynthesizedSource)
+        .build();
+    view_tile(&app, "/test/a/31/0/0.png").await;
+    view_tile(&app, "/test/a/50/0/0.png").await;
+    assert_eq!(admin_text(&app, "/admin/hot-areas").await, "test 2/0/0 1\n");
+}
+
+#[tokio::test]
+async fn hot_areas_are_not_counted_when_disabled() {
+    let app = RouterBuilder::new(hot_areas_config("enabled = false"))
+        .store(MemoryStore::new())
+        .source(SynthesizedSource)
+        .build();
+    view_tile(&app, "/test/a/3/4/2.png").await;
+    assert_eq!(admin_text(&app, "/admin/hot-areas").await, "");
+}
+
+#[tokio::test]
+async fn hot_areas_are_refreshed_and_extended() {
+    let source = RecordingSource::default();
+    let app = RouterBuilder::new(hot_areas_config("top_areas = 1\nring = 1\nlevels_down = 1"))
+        .store(MemoryStore::new())
+        .source(source.clone())
+        .build();
+    view_tile(&app, "/test/a/3/4/2.png").await;
+    view_tile(&app, "/test/a/3/4/2.png").await;
+    view_tile(&app, "/test/a/3/0/0.png").await;
+
+    // The area and its subtiles are downloaded again, even the stored one,
+    // and the 8 areas around it with their subtiles are downloaded
+    assert_eq!(
+        admin_text(&app, "/admin/hot-areas/refresh").await,
+        "Areas: 1, errors: 0, refreshed tiles: 5, new tiles: 40"
+    );
+    let fetched = source.fetched.lock().unwrap().clone();
+    assert_eq!(fetched.len(), 2 + 5 + 40);
+    assert_eq!(
+        fetched
+            .iter()
+            .filter(|tile| **tile == TileId::new(3, 4, 2))
+            .count(),
+        2
+    );
+
+    // Counts are halved after each refresh
+    assert_eq!(admin_text(&app, "/admin/hot-areas").await, "test 2/2/1 1\n");
+}
+
+/// Serves a differently shaded tile on every fetch, as if the map kept changing
+#[derive(Clone, Default)]
+struct ChangingSource {
+    fetches: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl TileSource for ChangingSource {
+    async fn fetch(
+        &self,
+        _style: &StyleConfig,
+        _idx: &str,
+        _tile: TileId,
+    ) -> Result<Vec<u8>, String> {
+        let fetches = self.fetches.fetch_add(1, Ordering::SeqCst);
+        Ok(png_tile(fetches as u8))
+    }
+}
+
+#[tokio::test]
+async fn refreshed_hot_areas_leave_no_stale_copies() {
+    let store = Arc::new(MemoryStore::new());
+    let app = RouterBuilder::new(hot_areas_config("top_areas = 1\nring = 0\nlevels_down = 0"))
+        .store(store.clone())
+        .source(ChangingSource::default())
+        .build();
+    for path in [
+        "/test/a/2/2/1.png",
+        "/test/a/2/2/1@2x.png",
+        "/test/a/2/2/1.webp",
+        "/test/a/2/2/1@2x.jpg",
+    ] {
+        view_tile(&app, path).await;
+    }
+    let tile = TileId::new(2, 2, 1);
+    let copies = [
+        tile,
+        tile.with_scale(2),
+        tile.with_format(TileFormat::Webp),
+        tile.with_scale(2).with_format(TileFormat::Jpeg),
+    ];
+    let mut before = vec![];
+    for copy in copies {
+        before.push(store.get("test", copy).await.unwrap().unwrap());
+    }
+
+    admin_text(&app, "/admin/hot-areas/refresh").await;
+    for (copy, old) in copies.into_iter().zip(before) {
+        let new = store.get("test", copy).await.unwrap().unwrap();
+        assert_ne!(new, old, "{copy:?}");
+    }
+    // No other copies are made along the way
+    assert_eq!(store.len(), 4);
+}
+
+#[tokio::test]
+async fn hot_areas_are_kept_across_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("counts.tsv");
+    let config = hot_areas_config(&format!("file = {:?}", file.to_str().unwrap()));
+
+    let app = RouterBuilder::new(config.clone())
+        .store(MemoryStore::new())
+        .source(RecordingSource::default())
+        .build();
+    for _ in 0..4 {
+        view_tile(&app, "/test/a/3/4/2.png").await;
+    }
+    admin_text(&app, "/admin/hot-areas/refresh").await;
+
+    let app = RouterBuilder::new(config)
+        .store(MemoryStore::new())
+        .source(OfflineSource)
+        .build();
+    assert_eq!(admin_text(&app, "/admin/hot-areas").await, "test 2/2/1 2\n");
+}
+
+#[tokio::test]
+async fn damaged_hot_areas_file_is_read_around_bad_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("counts.tsv");
+    std::fs::write(
+        &file,
+        "test\t2\t2\t1\t5\nnot a count\ntest\t40\t1\t1\t3\ntest\t200\t1\t1\t3\ntest\t3\t4\t2\t2\n",
+    )
+    .unwrap();
+    let config = hot_areas_config(&format!("file = {:?}", file.to_str().unwrap()));
+
+    let app = RouterBuilder::new(config)
+        .store(MemoryStore::new())
+        .source(OfflineSource)
+        .build();
+    assert_eq!(admin_text(&app, "/admin/hot-areas").await, "test 2/2/1 7\n");
+}
+
+async fn wmts_get(path: &str) -> (StatusCode, String, String) {
+    let resp = test_app("")
+        .await
+        .oneshot(
+            Request::get(path)
+                .header(header::HOST, "tiles.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let content_type = resp.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    (
+        status,
+        content_type,
+        String::from_utf8_lossy(&body).into_owned(),
+    )
+}
+
+#[tokio::test]
+async fn wmts_capabilities_list_styles_as_layers() {
+    let (status, content_type, kvp) = wmts_get("/wmts?service=WMTS&request=GetCapabilities").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/xml");
+    assert!(kvp.contains("<ows:Identifier>test</ows:Identifier>"));
+    assert!(kvp.contains("<ows:Identifier>GoogleMapsCompatible</ows:Identifier>"));
+    assert!(kvp.contains(r#"template="http://tiles.example.com/wmts/test/{Style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.png""#));
+    // The whole world at zoom 1 is 2 by 2 tiles
+    assert!(kvp.contains("<TileMatrix>1</TileMatrix>\n            <MinTileRow>0</MinTileRow>\n            <MaxTileRow>1</MaxTileRow>"));
+
+    let (status, _, rest) = wmts_get("/wmts/1.0.0/WMTSCapabilities.xml").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rest, kvp);
+}
+
+#[tokio::test]
+async fn too_deep_maxzoom_is_capped() {
+    let config = test_config_with_style("", "maxzoom = 64");
+    assert_eq!(config.styles[0].maxzoom, 31);
+
+    let app = RouterBuilder::new(config)
+        .store(MemoryStore::new())
+        .source(OfflineSource)
+        .build();
+    let (status, capabilities) = get_text(app, "/wmts/1.0.0/WMTSCapabilities.xml").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(capabilities.contains("<ows:Identifier>31</ows:Identifier>"));
+    assert!(!capabilities.contains("<ows:Identifier>32</ows:Identifier>"));
+}
+
+#[tokio::test]
+async fn wmts_tiles_come_from_the_store() {
+    let (status, content_type, body) = wmts_get(
+        "/wmts?SERVICE=WMTS&REQUEST=GetTile&LAYER=test&STYLE=default&TILEMATRIXSET=GoogleMapsCompatible&TILEMATRIX=1&TILEROW=1&TILECOL=0&FORMAT=image/png",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "image/png");
+    assert_eq!(body, "not really a png");
+
+    let (status, _, body) = wmts_get("/wmts/test/default/GoogleMapsCompatible/1/1/0.png").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "not really a png");
+}
+
+#[tokio::test]
+async fn wmts_errors_are_exception_reports() {
+    for (path, status, code) in [
+        (
+            "/wmts?SERVICE=WMTS&REQUEST=GetTile&LAYER=test",
+            StatusCode::BAD_REQUEST,
+            "MissingParameterValue",
+        ),
+        (
+            "/wmts?SERVICE=WMTS&REQUEST=GetFeatureInfo",
+            StatusCode::NOT_IMPLEMENTED,
+            "OperationNotSupported",
+        ),
+        (
+            "/wmts/nope/default/GoogleMapsCompatible/1/1/0.png",
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+        ),
+        (
+            "/wmts/test/default/GoogleMapsCompatible/1/2/0.png",
+            StatusCode::BAD_REQUEST,
+            "TileOutOfRange",
+        ),
+        (
+            "/wmts/test/default/GoogleMapsCompatible/20/0/0.png",
+            StatusCode::BAD_REQUEST,
+            "TileOutOfRange",
+        ),
+        (
+            "/wmts/test/default/EPSG:4326/1/1/0.png",
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+        ),
+    ] {
+        let (actual_status, content_type, body) = wmts_get(path).await;
+        assert_eq!(actual_status, status, "{path}");
+        assert_eq!(content_type, "application/xml");
+        assert!(
+            body.contains(&format!(r#"exceptionCode="{code}""#)),
+            "{path}: {body}"
+        );
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+#[test]
+fn local_mbtiles_files_are_not_online() {
+    use tile_cache::source::MbtilesSource;
+
+    assert!(!MbtilesSource::new(OfflineSource).is_online());
+    assert!(MbtilesSource::new(RecordingSource::default()).is_online());
+}
+
+#[cfg(feature = "mbtiles")]
+#[tokio::test]
+async fn tiles_are_read_from_local_mbtiles_file() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_cache::store::MbtilesStore::new(dir.path())
+        .put("local", TileId::new(1, 0, 1), &png_tile(100))
+        .await
+        .unwrap();
+    let path = dir.path().join("local.mbtiles");
+    let config: Config = toml::from_str(&format!(
+        r#"
+        precache_enabled = false
+
+        [[styles]]
+        name = "local"
+        url = "mbtiles:{}"
+        "#,
+        path.display()
+    ))
+    .unwrap();
+
+    // The source is picked by the config
+    let store = Arc::new(MemoryStore::new());
+    let app = RouterBuilder::new(config).store(store.clone()).build();
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::get("/local/a/1/0/1.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    // Highlighted fresh tiles are recolored, and not to be kept by clients
+    if cfg!(not(feature = "debug-highlight-fresh")) {
+        assert!(resp.headers().contains_key(header::ETAG));
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body.as_ref(), png_tile(100));
+    }
+    assert_eq!(
+        store.get("local", TileId::new(1, 0, 1)).await.unwrap(),
+        Some(png_tile(100))
+    );
+
+    // Missing tiles are errors rather than blank tiles, so they are not stored
+    let resp = app
+        .oneshot(
+            Request::get("/local/a/1/1/1.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(!resp.headers().contains_key(header::ETAG));
+    assert_eq!(
+        store.get("local", TileId::new(1, 1, 1)).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn snapshots_keep_tiles_as_they_were() {
+    let store = Arc::new(MemoryStore::new());
+    let source = CountingSource::default();
+    let app = RouterBuilder::new(test_config(
+        "precache_enabled = false\n[admin]\ntoken = \"secret\"",
+    ))
+    .store(store.clone())
+    .source(source.clone())
+    .build();
+    store
+        .put("test", TileId::new(1, 0, 1), b"old road")
+        .await
+        .unwrap();
+
+    let admin = |path: &str| {
+        Request::get(path)
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let resp = app
+        .clone()
+        .oneshot(admin(
+            "/admin/snapshot/test/2023-09?bbox=-180,-85,180,85&maxzoom=1",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    // Tiles are copied in the background
+    let snapshot_tile = || store.get("test@2023-09", TileId::new(1, 0, 1));
+    for _ in 0..100 {
+        if snapshot_tile().await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(snapshot_tile().await.unwrap(), Some(b"old road".to_vec()));
+    for name in ["2x", "../other", "sep%202023"] {
+        let path = format!("/admin/snapshot/test/{name}?bbox=-180,-85,180,85");
+        let resp = app.clone().oneshot(admin(&path)).await.unwrap();
+        assert!(resp.status().is_client_error(), "{name}");
+    }
+
+    store
+        .put("test", TileId::new(1, 0, 1), b"new road")
+        .await
+        .unwrap();
+    let body_of = |path: &'static str| {
+        let app = app.clone();
+        async move {
+            let resp = app
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            hyper::body::to_bytes(resp.into_body()).await.unwrap()
+        }
+    };
+    assert_eq!(
+        body_of("/test@2023-09/a/1/0/1.png").await.as_ref(),
+        b"old road"
+    );
+    assert_eq!(body_of("/test/a/1/0/1.png").await.as_ref(), b"new road");
+
+    // Only snapshots that were made are served
+    for path in [
+        "/test@2024-01/a/1/0/1.png",
+        "/test@2x/a/1/0/1.png",
+        "/test@bad.name/a/1/0/1.png",
+        "/other@2023-09/a/1/0/1.png",
+    ] {
+        let resp = app
+            .clone()
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+
+    // Nothing is downloaded into snapshots
+    let missing = body_of("/test@2023-09/a/1/1/1.png").await;
+    assert!(image::load_from_memory(&missing).is_ok());
+    assert_eq!(source.fetches.load(Ordering::SeqCst), 0);
+    assert_eq!(
+        store
+            .get("test@2023-09", TileId::new(1, 1, 1))
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn style_names_cannot_look_like_snapshots() {
+    let config = toml::from_str::<Config>(
+        r#"
+        [[styles]]
+        name = "test@2023-09"
+        url = "http://127.0.0.1:9/{z}/{x}/{y}.png"
+        "#,
+    );
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn tms_route_flips_rows() {
+    let resp = test_app("")
+        .await
+        .oneshot(
+            Request::get("/tms/test/a/1/0/0.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(body.as_ref(), b"not really a png");
+
+    for path in [
+        "/tms/test/a/1/0/2.png",
+        "/tms/test/a/40/0/0.png",
+        "/tms/test/a/1/0/x.png",
+    ] {
+        let resp = test_app("")
+            .await
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn tiles_off_the_map_are_bad_requests() {
+    for path in [
+        "/test/a/1/2/0.png",
+        "/test/a/1/0/2.png",
+        "/test/a/32/0/0.png",
+        "/test/a/255/0/0.png",
+    ] {
+        let resp = test_app("")
+            .await
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+    }
+}
+
+#[test]
+fn tile_ids_convert_to_other_schemes() {
+    let tile = TileId::new(3, 3, 5);
+    assert_eq!(tile.quadkey(), "213");
+    assert_eq!(tile.tms_y(), Some(2));
+    assert_eq!(TileId::new(3, 3, 8).tms_y(), None);
+    assert_eq!(TileId::new(40, 0, 0).tms_y(), None);
+    assert_eq!(TileId::new(0, 0, 0).quadkey(), "");
+}
+
+#[cfg(feature = "online")]
+#[test]
+fn upstream_urls_follow_the_scheme() {
+    let url = |scheme: &str| {
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [[styles]]
+            name = "test"
+            url = "http://upstream/{{z}}/{{x}}/{{y}}?q={{q}}"
+            {scheme}
+            "#
+        ))
+        .unwrap();
+        config.styles[0].tile_url("a", TileId::new(3, 3, 5))
+    };
+    assert_eq!(url("").unwrap(), "http://upstream/3/3/5?q=213");
+    assert_eq!(
+        url(r#"scheme = "tms""#).unwrap(),
+        "http://upstream/3/3/2?q=213"
+    );
+}
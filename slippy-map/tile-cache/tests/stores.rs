@@ -0,0 +1,247 @@
+use tile_cache::{
+    store::{DirectoryStore, MemoryStore, StoreStats},
+    TileId, TileStore,
+};
+
+async fn check_round_trip(store: &dyn TileStore) {
+    let tile = TileId::new(3, 5, 1);
+    assert_eq!(store.get("_", tile).await.unwrap(), None);
+
+    store.put("_", tile, b"first").await.unwrap();
+    assert_eq!(
+        store.get("_", tile).await.unwrap().as_deref(),
+        Some(&b"first"[..])
+    );
+
+    // Same position in another style is a different tile
+    assert_eq!(store.get("matrix", tile).await.unwrap(), None);
+
+    store.put("_", tile, b"second").await.unwrap();
+    assert_eq!(
+        store.get("_", tile).await.unwrap().as_deref(),
+        Some(&b"second"[..])
+    );
+}
+
+#[tokio::test]
+async fn directory_store_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    check_round_trip(&DirectoryStore::new(dir.path())).await;
+    assert_eq!(
+        std::fs::read(dir.path().join("_/3/5/1.png")).unwrap(),
+        b"second"
+    );
+}
+
+#[tokio::test]
+async fn memory_store_round_trip() {
+    let store = MemoryStore::new();
+    check_round_trip(&store).await;
+    assert_eq!(store.len(), 1);
+}
+
+#[cfg(feature = "mbtiles")]
+#[tokio::test]
+async fn mbtiles_store_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    check_round_trip(&tile_cache::store::MbtilesStore::new(dir.path())).await;
+    assert!(dir.path().join("_.mbtiles").exists());
+    // Reading a style that has nothing stored creates no file
+    assert!(!dir.path().join("matrix.mbtiles").exists());
+}
+
+/// Three identical tiles and a different one
+async fn put_duplicates(store: &dyn TileStore) {
+    for x in 0..3 {
+        store
+            .put("_", TileId::new(2, x, 0), b"empty sea")
+            .await
+            .unwrap();
+    }
+    store
+        .put("matrix", TileId::new(2, 0, 0), b"land")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn deduplicated_directory_store_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    check_round_trip(&DirectoryStore::new(dir.path()).dedup(true)).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn directory_store_deduplicates_identical_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = DirectoryStore::new(dir.path()).dedup(true);
+    put_duplicates(&store).await;
+
+    assert_eq!(
+        store.stats().await.unwrap(),
+        StoreStats {
+            tiles: 4,
+            unique_tiles: 2,
+            total_bytes: 3 * 9 + 4,
+            stored_bytes: 9 + 4,
+        }
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("_/2/1/0.png")).unwrap(),
+        b"empty sea"
+    );
+    assert_eq!(
+        std::fs::read_dir(dir.path().join(".blobs"))
+            .unwrap()
+            .count(),
+        2
+    );
+
+    // Overwriting a tile with the contents it already has changes nothing
+    store
+        .put("_", TileId::new(2, 0, 0), b"empty sea")
+        .await
+        .unwrap();
+    assert_eq!(store.stats().await.unwrap().tiles, 4);
+}
+
+#[tokio::test]
+async fn directory_store_without_dedup_keeps_copies() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = DirectoryStore::new(dir.path());
+    put_duplicates(&store).await;
+
+    let stats = store.stats().await.unwrap();
+    assert_eq!((stats.tiles, stats.unique_tiles), (4, 4));
+    assert_eq!(stats.saved_bytes(), 0);
+    assert!(!dir.path().join(".blobs").exists());
+}
+
+#[tokio::test]
+async fn memory_store_shares_identical_tiles() {
+    let store = MemoryStore::new();
+    put_duplicates(&store).await;
+    assert_eq!(
+        store.stats().await.unwrap(),
+        StoreStats {
+            tiles: 4,
+            unique_tiles: 2,
+            total_bytes: 3 * 9 + 4,
+            stored_bytes: 9 + 4,
+        }
+    );
+
+    // The old contents are dropped once no tile has them
+    store
+        .put("matrix", TileId::new(2, 0, 0), b"empty sea")
+        .await
+        .unwrap();
+    let stats = store.stats().await.unwrap();
+    assert_eq!(
+        (stats.tiles, stats.unique_tiles, stats.stored_bytes),
+        (4, 1, 9)
+    );
+}
+
+#[cfg(feature = "mbtiles")]
+#[tokio::test]
+async fn deduplicated_mbtiles_store_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    check_round_trip(&tile_cache::store::MbtilesStore::new(dir.path()).dedup(true)).await;
+}
+
+#[cfg(feature = "mbtiles")]
+#[tokio::test]
+async fn mbtiles_store_deduplicates_identical_tiles() {
+    use tile_cache::store::MbtilesStore;
+
+    let dir = tempfile::tempdir().unwrap();
+    put_duplicates(&MbtilesStore::new(dir.path()).dedup(true)).await;
+    assert_eq!(
+        MbtilesStore::new(dir.path()).stats().await.unwrap(),
+        StoreStats {
+            tiles: 4,
+            unique_tiles: 2,
+            total_bytes: 3 * 9 + 4,
+            stored_bytes: 9 + 4,
+        }
+    );
+
+    // Existing files keep their layout, whatever the setting
+    let store = MbtilesStore::new(dir.path());
+    assert_eq!(
+        store
+            .get("_", TileId::new(2, 2, 0))
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(&b"empty sea"[..])
+    );
+    store
+        .put("_", TileId::new(2, 3, 0), b"empty sea")
+        .await
+        .unwrap();
+    assert_eq!(store.stats().await.unwrap().unique_tiles, 2);
+}
+
+/// Copies the only stored tile, and checks that copies are independent from later changes
+async fn check_copy(store: &dyn TileStore) {
+    let tile = TileId::new(3, 5, 1);
+    store.put("_", tile, b"before").await.unwrap();
+    assert!(!store.has_style("_@2023-09").await.unwrap());
+
+    assert!(store.copy("_", "_@2023-09", tile).await.unwrap());
+    assert!(store.has_style("_@2023-09").await.unwrap());
+    assert!(!store.has_style("_@2023").await.unwrap());
+    assert!(!store
+        .copy("_", "_@2023-09", TileId::new(3, 5, 2))
+        .await
+        .unwrap());
+    // Tiles already copied stay as they are
+    store.put("_", tile, b"after").await.unwrap();
+    assert!(!store.copy("_", "_@2023-09", tile).await.unwrap());
+
+    assert_eq!(
+        store.get("_@2023-09", tile).await.unwrap().as_deref(),
+        Some(&b"before"[..])
+    );
+    assert_eq!(
+        store.get("_", tile).await.unwrap().as_deref(),
+        Some(&b"after"[..])
+    );
+}
+
+#[tokio::test]
+async fn directory_store_copies_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    check_copy(&DirectoryStore::new(dir.path())).await;
+}
+
+#[tokio::test]
+async fn memory_store_copies_tiles() {
+    check_copy(&MemoryStore::new()).await;
+}
+
+#[cfg(feature = "mbtiles")]
+#[tokio::test]
+async fn mbtiles_store_copies_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    check_copy(&tile_cache::store::MbtilesStore::new(dir.path())).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn directory_store_copies_share_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = DirectoryStore::new(dir.path());
+    put_duplicates(&store).await;
+    for x in 0..3 {
+        store
+            .copy("_", "_@2023-09", TileId::new(2, x, 0))
+            .await
+            .unwrap();
+    }
+
+    let stats = store.stats().await.unwrap();
+    assert_eq!((stats.tiles, stats.unique_tiles), (7, 4));
+}
@@ -0,0 +1,261 @@
+mod common;
+
+#[cfg(feature = "online")]
+use common::tile_png;
+use common::{MockUpstream, TileCache};
+
+async fn image_size(resp: reqwest::Response) -> (u32, u32) {
+    assert_eq!(resp.status(), 200);
+    let body = resp.bytes().await.unwrap();
+    let image = image::load_from_memory(&body).unwrap();
+    (image.width(), image.height())
+}
+
+/// Error tiles are 256x256 PNGs
+async fn assert_error_tile(resp: reqwest::Response) {
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    assert_eq!(resp.headers()["Cache-Control"], "no-cache, no-store");
+    let body = resp.bytes().await.unwrap();
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (256, 256));
+}
+
+#[tokio::test]
+async fn bad_tile_coordinates_are_rejected() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    for path in [
+        "/_/a/1/0/abc.png",
+        "/_/a/1/0/.png",
+        "/_/a/1/0/-1.png",
+        "/_/a/zoom/0/0.png",
+        "/_/a/1/x/0.png",
+        "/_/a/1/0/0@0x.png",
+        "/_/a/1/0/0@9x.png",
+        "/_/a/1/0/0@2.png",
+        "/_/a/1/0/0.gif",
+    ] {
+        let resp = server.get(path).await;
+        assert_eq!(resp.status(), 400, "{path} should be rejected");
+    }
+    assert!(upstream.hits().is_empty());
+}
+
+#[tokio::test]
+async fn cached_tile_is_served_from_disk() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+    server.seed("matrix", 4, 3, 2, b"seeded tile");
+
+    let resp = server.get("/matrix/a/4/3/2.png").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    assert_eq!(
+        resp.headers()["Cache-Control"],
+        "max-age=604800, public, immutable"
+    );
+    assert!(resp.headers().contains_key("ETag"));
+    assert_eq!(resp.bytes().await.unwrap().as_ref(), b"seeded tile");
+
+    assert!(!upstream
+        .hits()
+        .iter()
+        .any(|p| p.ends_with("/jawg-matrix/4/3/2.png")));
+}
+
+#[tokio::test]
+async fn unknown_style_gives_error_tile() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    assert_error_tile(server.get("/nonexistent/a/1/0/0.png").await).await;
+}
+
+#[tokio::test]
+async fn retina_error_tile_is_512px() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/nonexistent/a/1/0/0@2x.png").await;
+    assert_eq!(image_size(resp).await, (512, 512));
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn retina_tile_comes_from_retina_url() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/transportdark/a/3/1/2@2x.png").await;
+    assert_eq!(image_size(resp).await, (512, 512));
+
+    assert!(upstream.was_hit("/thunderforest/a/transport-dark/3/1/2@2x.png"));
+    assert!(server.cache_dir.join("transportdark/3/1/2@2x.png").exists());
+    // The regular tile is cached separately
+    assert_eq!(server.cached_tile("transportdark", 3, 1, 2), None);
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn retina_tile_is_upscaled_without_retina_url() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/_/a/3/1/2@2x.png").await;
+    assert_eq!(image_size(resp).await, (512, 512));
+
+    assert!(upstream.was_hit("/osm/3/1/2.png"));
+    assert!(!upstream.hits().iter().any(|p| p.contains("@2x")));
+    assert_eq!(server.cached_tile("_", 3, 1, 2).unwrap(), tile_png(3, 1, 2));
+    assert!(server.cache_dir.join("_/3/1/2@2x.png").exists());
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn converted_tile_is_stored_next_to_png() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/_/a/3/2/1.webp").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "image/webp");
+
+    assert!(upstream.was_hit("/osm/3/2/1.png"));
+    assert!(!upstream.hits().iter().any(|p| p.ends_with(".webp")));
+    assert_eq!(server.cached_tile("_", 3, 2, 1).unwrap(), tile_png(3, 2, 1));
+    assert!(server.cache_dir.join("_/3/2/1.webp").exists());
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn missing_tile_is_downloaded_and_stored() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/_/a/3/2/1.png").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    let body = resp.bytes().await.unwrap();
+
+    assert!(upstream.was_hit("/osm/3/2/1.png"));
+    assert_eq!(server.cached_tile("_", 3, 2, 1).unwrap(), tile_png(3, 2, 1));
+    #[cfg(not(feature = "debug-highlight-fresh"))]
+    assert_eq!(body.as_ref(), tile_png(3, 2, 1));
+    #[cfg(feature = "debug-highlight-fresh")]
+    let _ = body;
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn upstream_url_gets_subdomain_and_key() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/transportdark/b/2/1/1.png").await;
+    assert_eq!(resp.status(), 200);
+    assert!(
+        upstream.was_hit("/thunderforest/b/transport-dark/2/1/1.png"),
+        "{:?}",
+        upstream.hits()
+    );
+    assert_eq!(
+        server.cached_tile("transportdark", 2, 1, 1).unwrap(),
+        tile_png(2, 1, 1)
+    );
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn fresh_tile_cache_headers() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/_/a/2/0/0.png").await;
+    if cfg!(feature = "debug-highlight-fresh") {
+        assert_eq!(resp.headers()["Cache-Control"], "no-cache, no-store");
+        assert!(!resp.headers().contains_key("ETag"));
+    } else {
+        assert_eq!(
+            resp.headers()["Cache-Control"],
+            "max-age=604800, public, immutable"
+        );
+        assert!(resp.headers().contains_key("ETag"));
+    }
+
+    // The second time, the tile comes from disk and is always cacheable
+    let resp = server.get("/_/a/2/0/0.png").await;
+    assert_eq!(
+        resp.headers()["Cache-Control"],
+        "max-age=604800, public, immutable"
+    );
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn upstream_failure_gives_error_tile() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    assert_error_tile(server.get("/broken/a/5/4/3.png").await).await;
+    assert!(upstream.was_hit("/broken/5/4/3.png"));
+    assert!(server.cached_tile("broken", 5, 4, 3).is_none());
+}
+
+#[cfg(feature = "online")]
+#[tokio::test]
+async fn precache_counts_tiles() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+    server.seed("_", 1, 0, 0, &tile_png(1, 0, 0));
+
+    let resp = server.admin_get("/precache-until-zoom/_/1").await;
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "Errors: 0, existing tiles: 1, new tiles: 4"
+    );
+
+    let resp = server.admin_get("/precache-until-zoom/_/1").await;
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "Errors: 0, existing tiles: 5, new tiles: 0"
+    );
+
+    let resp = server.admin_get("/precache-until-zoom/broken/1").await;
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "Errors: 5, existing tiles: 0, new tiles: 0"
+    );
+}
+
+#[tokio::test]
+async fn admin_routes_need_token() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    let resp = server.get("/admin/precache-until-zoom/_/1").await;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(resp.headers()["WWW-Authenticate"], "Bearer");
+    // Not served as a tile route either
+    assert_eq!(server.get("/precache-until-zoom/_/1").await.status(), 404);
+    assert!(upstream.hits().is_empty());
+}
+
+#[cfg(not(feature = "online"))]
+#[tokio::test]
+async fn offline_build_never_fetches() {
+    let upstream = MockUpstream::start().await;
+    let server = TileCache::start(&upstream).await;
+
+    assert_error_tile(server.get("/_/a/3/2/1.png").await).await;
+
+    let resp = server.admin_get("/precache-until-zoom/_/1").await;
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "Tile source is offline, cannot perform any fetch"
+    );
+
+    assert!(upstream.hits().is_empty());
+    assert!(server.cached_tile("_", 3, 2, 1).is_none());
+}
@@ -0,0 +1,136 @@
+//! Settings read at startup from `config.json`, served next to `index.html`,
+//! so that one build can be deployed against any backend and tile server.
+//! The titles and attributions of map styles come from the tile server's `styles.json`.
+
+use serde::Deserialize;
+
+/// Name of the file, relative to the page
+const CONFIG_FILE: &str = "config.json";
+
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// Where the backend is listening, like `http://10.69.69.3:8080`
+    pub backend_url: String,
+    /// Where the tile cache is listening, like `http://localhost:3000`
+    pub tile_server_url: String,
+    /// Where the map opens
+    pub initial_view: InitialView,
+    /// Map styles to offer, by their name in the tile cache, the first one being shown at startup
+    pub layers: Vec<LayerConfig>,
+}
+
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct InitialView {
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: f64,
+}
+
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(from = "String")]
+pub struct LayerConfig {
+    /// Style name in the tile cache
+    pub name: String,
+    /// Button label, the name until the tile cache has described the style
+    pub title: String,
+    pub attribution: String,
+}
+
+impl From<String> for LayerConfig {
+    fn from(name: String) -> Self {
+        LayerConfig {
+            title: name.clone(),
+            name,
+            attribution: String::new(),
+        }
+    }
+}
+
+/// A style as listed in the tile cache's `styles.json`, in TileJSON
+#[derive(Deserialize)]
+struct StyleInfo {
+    id: String,
+    name: String,
+    #[serde(default)]
+    attribution: String,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            backend_url: "http://localhost:8080".to_string(),
+            tile_server_url: "http://localhost:3000".to_string(),
+            initial_view: InitialView::default(),
+            layers: ["_", "transportdark", "matrix"]
+                .map(|name| LayerConfig::from(name.to_string()))
+                .into(),
+        }
+    }
+}
+
+impl Default for InitialView {
+    fn default() -> Self {
+        InitialView {
+            lat: 55.34,
+            lon: 37.78,
+            zoom: 11.0,
+        }
+    }
+}
+
+/// Fetch `config.json`. If it cannot be read, the defaults for a local setup are used,
+/// so that the map still comes up.
+pub async fn load() -> AppConfig {
+    let mut config = match fetch().await {
+        Ok(config) => config,
+        Err(why) => {
+            log::warn!("Could not load {CONFIG_FILE}, using defaults: {why:#}");
+            AppConfig::default()
+        }
+    };
+    match fetch_styles(&config.tile_server_url).await {
+        Ok(styles) => describe_layers(&mut config.layers, styles),
+        Err(why) => log::warn!("Could not load the map styles, showing their names: {why:#}"),
+    }
+    config
+}
+
+/// Title the layers and set their attribution from the tile cache's description of the styles.
+/// Layers whose style the tile cache does not have are left out.
+fn describe_layers(layers: &mut Vec<LayerConfig>, styles: Vec<StyleInfo>) {
+    layers.retain_mut(|layer| {
+        let Some(style) = styles.iter().find(|style| style.id == layer.name) else {
+            log::warn!("The tile server has no map style {}", layer.name);
+            return false;
+        };
+        layer.title = style.name.clone();
+        layer.attribution = style.attribution.clone();
+        true
+    });
+}
+
+async fn fetch_styles(tile_server_url: &str) -> anyhow::Result<Vec<StyleInfo>> {
+    let url = format!("{}/styles.json", tile_server_url.trim_end_matches('/'));
+    let styles = reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .json::<Vec<StyleInfo>>()
+        .await?;
+    Ok(styles)
+}
+
+async fn fetch() -> anyhow::Result<AppConfig> {
+    // Requests need an absolute URL, so resolve the file against the page
+    let page = gloo_utils::document()
+        .url()
+        .map_err(|why| anyhow::anyhow!("Could not get the page URL: {why:?}"))?;
+    let url = reqwest::Url::parse(&page)?.join(CONFIG_FILE)?;
+
+    let config = reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .json::<AppConfig>()
+        .await?;
+    Ok(config)
+}
@@ -0,0 +1,86 @@
+//! Classes of damage, as users pick and read them.
+
+use common_data::DamageType;
+
+pub struct DamageClass {
+    /// Short name used in URLs
+    pub key: &'static str,
+    pub label: &'static str,
+    /// A damage of this class, to show its icon
+    pub example: DamageType,
+}
+
+/// Every class, in the order they are listed; damages of types added to the backend later are `other`
+pub fn damage_classes() -> [DamageClass; 9] {
+    [
+        DamageClass {
+            key: "pothole",
+            label: "Rutting/bump/pothole/separation",
+            example: DamageType::Rutting_bump_pothole_separation,
+        },
+        DamageClass {
+            key: "hatch",
+            label: "Utility hole/maintenance hatch",
+            example: DamageType::Utility_hole_maintenance_hatch,
+        },
+        DamageClass {
+            key: "alligator",
+            label: "Alligator crack",
+            example: DamageType::Alligator_crack,
+        },
+        DamageClass {
+            key: "longitudinal",
+            label: "Linear longitudinal crack",
+            example: DamageType::Linear_longitudinal_crack,
+        },
+        DamageClass {
+            key: "lateral",
+            label: "Linear lateral crack",
+            example: DamageType::Linear_lateral_crack,
+        },
+        DamageClass {
+            key: "line",
+            label: "White line blur",
+            example: DamageType::White_line_blur,
+        },
+        DamageClass {
+            key: "crosswalk",
+            label: "Cross walk blur",
+            example: DamageType::Cross_walk_blur,
+        },
+        DamageClass {
+            key: "repair",
+            label: "Repair",
+            example: DamageType::Repair,
+        },
+        DamageClass {
+            key: "other",
+            label: "Unknown",
+            example: DamageType::Repair,
+        },
+    ]
+}
+
+/// Key of the damage's class
+pub fn damage_class(damage_type: &DamageType) -> &'static str {
+    match damage_type {
+        DamageType::Rutting_bump_pothole_separation => "pothole",
+        DamageType::Utility_hole_maintenance_hatch => "hatch",
+        DamageType::Alligator_crack => "alligator",
+        DamageType::Linear_longitudinal_crack => "longitudinal",
+        DamageType::Linear_lateral_crack => "lateral",
+        DamageType::White_line_blur => "line",
+        DamageType::Cross_walk_blur => "crosswalk",
+        DamageType::Repair => "repair",
+        _ => "other",
+    }
+}
+
+/// Human readable name of the damage's class
+pub fn damage_label(damage_type: &DamageType) -> &'static str {
+    let key = damage_class(damage_type);
+    damage_classes()
+        .into_iter()
+        .find(|class| class.key == key)
+        .map_or("Unknown", |class| class.label)
+}
@@ -0,0 +1,139 @@
+use std::cmp::Ordering;
+
+use common_data::RoadDamage;
+use yew::prelude::*;
+
+use crate::{damage::damage_label, leaflet::icons::ShowIcon};
+
+const PAGE_SIZE: usize = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortBy {
+    Id,
+    Kind,
+    Latitude,
+    Longitude,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct DamageTableProps {
+    /// Damages in view
+    pub damages: Vec<RoadDamage>,
+    pub selected: Option<u64>,
+    pub on_select: Callback<RoadDamage>,
+}
+
+/// Sortable list of the damages in view, a page at a time
+#[function_component]
+pub fn DamageTable(props: &DamageTableProps) -> Html {
+    let sort = use_state(|| (SortBy::Id, true));
+    let page = use_state(|| 0);
+
+    let mut damages = props.damages.clone();
+    let (sort_by, ascending) = *sort;
+    damages.sort_by(|a, b| {
+        let order = match sort_by {
+            SortBy::Id => a.id.cmp(&b.id),
+            SortBy::Kind => damage_label(&a.damage_type).cmp(damage_label(&b.damage_type)),
+            SortBy::Latitude => a
+                .latitude
+                .partial_cmp(&b.latitude)
+                .unwrap_or(Ordering::Equal),
+            SortBy::Longitude => a
+                .longitude
+                .partial_cmp(&b.longitude)
+                .unwrap_or(Ordering::Equal),
+        }
+        .then_with(|| a.id.cmp(&b.id));
+        if ascending {
+            order
+        } else {
+            order.reverse()
+        }
+    });
+
+    // The map may have moved to fewer damages since the page was picked
+    let pages = damages.len().div_ceil(PAGE_SIZE).max(1);
+    let current_page = (*page).min(pages - 1);
+
+    let header = |title: &'static str, column: SortBy| {
+        let onclick = {
+            let sort = sort.clone();
+            let page = page.clone();
+            Callback::from(move |_: MouseEvent| {
+                // Clicking the sorted column again reverses the order
+                let ascending = sort.0 != column || !sort.1;
+                sort.set((column, ascending));
+                page.set(0);
+            })
+        };
+        let arrow = match *sort {
+            (sort_by, true) if sort_by == column => " ▲",
+            (sort_by, false) if sort_by == column => " ▼",
+            _ => "",
+        };
+        html!(<th scope="col" style="cursor: pointer;" {onclick}>{title}{arrow}</th>)
+    };
+
+    let rows = damages
+        .iter()
+        .skip(current_page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(|damage| {
+            let onclick = {
+                let on_select = props.on_select.clone();
+                let damage = damage.clone();
+                Callback::from(move |_: MouseEvent| on_select.emit(damage.clone()))
+            };
+            let selected = props.selected == Some(damage.id);
+            html! {
+                <tr class={classes!(selected.then_some("table-active"))} style="cursor: pointer;" {onclick}>
+                    <td>{damage.id}</td>
+                    <td>
+                        <span style="position: relative; display: inline-block; width: 1.5em; height: 1.5em; vertical-align: middle;">
+                            <ShowIcon damage_type={damage.damage_type.clone()} />
+                        </span>
+                        {" "}{damage_label(&damage.damage_type)}
+                    </td>
+                    <td>{format!("{:.5}", damage.latitude)}</td>
+                    <td>{format!("{:.5}", damage.longitude)}</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    let go_to_page = |target: usize| {
+        let page = page.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            page.set(target);
+        })
+    };
+
+    html! {
+        <>
+            <table class="table table-sm table-hover">
+                <thead>
+                    <tr>
+                        {header("Id", SortBy::Id)}
+                        {header("Type", SortBy::Kind)}
+                        {header("Latitude", SortBy::Latitude)}
+                        {header("Longitude", SortBy::Longitude)}
+                    </tr>
+                </thead>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+            <div class="d-flex align-items-center gap-2">
+                <button class="btn btn-sm btn-outline-secondary" disabled={current_page == 0} onclick={go_to_page(current_page.saturating_sub(1))}>
+                    {"Previous"}
+                </button>
+                <span>{format!("Page {} of {pages}, {} damages in view", current_page + 1, damages.len())}</span>
+                <button class="btn btn-sm btn-outline-secondary" disabled={current_page + 1 >= pages} onclick={go_to_page(current_page + 1)}>
+                    {"Next"}
+                </button>
+            </div>
+        </>
+    }
+}
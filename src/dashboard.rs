@@ -0,0 +1,349 @@
+//! Charts of all the damages, for an overview without the map.
+
+mod charts;
+
+use std::{collections::HashMap, rc::Rc};
+
+use common_data::RoadDamage;
+use yew::prelude::*;
+use yew_hooks::{use_async, use_async_with_options, UseAsyncOptions};
+use yew_router::prelude::*;
+
+use crate::{
+    config::AppConfig,
+    damage::{damage_class, damage_classes, damage_label},
+    leaflet::icons::{icon_color, icon_name},
+    permalink::{Permalink, Route},
+};
+use charts::{Bar, BarChart, HeatCell, HeatGrid, Histogram};
+
+/// Side of the squares damages are grouped into by area, in degrees of latitude (about 1 km)
+const AREA_CELL: f64 = 0.01;
+/// Length of a degree of latitude, in km
+const KM_PER_DEGREE: f64 = 111.2;
+/// Most rows or columns of the area grid; squares are merged to fit larger regions
+const MAX_GRID_SIZE: f64 = 120.0;
+/// Zoom the map opens at when an area is picked
+const AREA_ZOOM: f64 = 15.0;
+/// Number of areas listed as the worst
+const WORST_AREAS: usize = 10;
+/// Number of damages whose certainty is fetched at a time, one request each
+const CERTAINTY_BATCH: usize = 50;
+/// Number of columns in the certainty histogram
+const CERTAINTY_BINS: usize = 10;
+
+/// The whole world, to fetch all the damages
+fn world() -> frontend_requests::AABB {
+    frontend_requests::AABB {
+        p1: (180.0, 90.0),
+        p2: (-180.0, -90.0),
+    }
+}
+
+/// Damages grouped into squares of a grid around all of them
+#[derive(Default)]
+struct AreaGrid {
+    rows: usize,
+    columns: usize,
+    /// North-west corner of the grid, in degrees
+    north: f64,
+    west: f64,
+    /// Size of the squares in degrees, so that they are about square on the ground
+    cell_height: f64,
+    cell_width: f64,
+    /// Damages in each square, by row from the north and column from the west
+    cells: HashMap<(usize, usize), Vec<RoadDamage>>,
+}
+
+impl AreaGrid {
+    fn new(damages: &[RoadDamage]) -> Self {
+        if damages.is_empty() {
+            return AreaGrid::default();
+        }
+        let north = damages
+            .iter()
+            .map(|damage| damage.latitude)
+            .fold(f64::MIN, f64::max);
+        let south = damages
+            .iter()
+            .map(|damage| damage.latitude)
+            .fold(f64::MAX, f64::min);
+        let east = damages
+            .iter()
+            .map(|damage| damage.longitude)
+            .fold(f64::MIN, f64::max);
+        let west = damages
+            .iter()
+            .map(|damage| damage.longitude)
+            .fold(f64::MAX, f64::min);
+
+        // Squares are merged so that the grid fits in `MAX_GRID_SIZE` squares a side
+        let width = AREA_CELL / ((north + south) / 2.0).to_radians().cos().max(0.01);
+        let merge = ((north - south) / AREA_CELL)
+            .max((east - west) / width)
+            .div_euclid(MAX_GRID_SIZE)
+            + 1.0;
+        let cell_height = AREA_CELL * merge;
+        let cell_width = width * merge;
+
+        let mut cells: HashMap<_, Vec<_>> = HashMap::new();
+        for damage in damages {
+            let row = ((north - damage.latitude) / cell_height) as usize;
+            let column = ((damage.longitude - west) / cell_width) as usize;
+            cells.entry((row, column)).or_default().push(damage.clone());
+        }
+        AreaGrid {
+            rows: ((north - south) / cell_height) as usize + 1,
+            columns: ((east - west) / cell_width) as usize + 1,
+            north,
+            west,
+            cell_height,
+            cell_width,
+            cells,
+        }
+    }
+
+    fn cell_center(&self, (row, column): (usize, usize)) -> (f64, f64) {
+        (
+            self.north - (row as f64 + 0.5) * self.cell_height,
+            self.west + (column as f64 + 0.5) * self.cell_width,
+        )
+    }
+}
+
+/// Label of the most common damage class among the damages
+fn most_common(damages: &[RoadDamage]) -> &'static str {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for damage in damages {
+        *counts.entry(damage_label(&damage.damage_type)).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(label, count)| (count, std::cmp::Reverse(label)))
+        .map_or("", |(label, _)| label)
+}
+
+fn card(title: &'static str, body: Html) -> Html {
+    html! {
+        <div class="col-xl-6">
+            <div class="card h-100">
+                <div class="card-body">
+                    <h5 class="card-title">{title}</h5>
+                    {body}
+                </div>
+            </div>
+        </div>
+    }
+}
+
+fn spinner() -> Html {
+    html! {
+        <div class="d-flex justify-content-center my-5">
+            <div class="spinner-border" role="status"></div>
+        </div>
+    }
+}
+
+#[function_component]
+pub fn Dashboard() -> Html {
+    let config = use_context::<Rc<AppConfig>>().expect("App provides the config");
+
+    let damages = use_async_with_options(
+        {
+            let backend_url = config.backend_url.clone();
+            async move {
+                let damages = frontend_requests::get_points_in_rect(&backend_url, world()).await?;
+                Ok::<_, Rc<anyhow::Error>>(Rc::new(damages))
+            }
+        },
+        UseAsyncOptions::enable_auto(),
+    );
+
+    // Certainty is only known point by point, so it is fetched for every damage,
+    // a batch at a time, in the order of the list; `None` where it could not be fetched
+    let certainties = use_state(Vec::<Option<f64>>::new);
+    let next_batch: Vec<u64> = damages
+        .data
+        .as_ref()
+        .map(|damages| {
+            damages
+                .iter()
+                .skip(certainties.len())
+                .take(CERTAINTY_BATCH)
+                .map(|damage| damage.id)
+                .collect()
+        })
+        .unwrap_or_default();
+    let fetch_certainties = use_async({
+        let certainties = certainties.clone();
+        let backend_url = config.backend_url.clone();
+        let batch = next_batch.clone();
+        async move {
+            let mut fetched = (*certainties).clone();
+            for id in batch {
+                let certainty = match frontend_requests::get_info_by_id(&backend_url, id).await {
+                    Ok(info) => Some(info.top_certainty),
+                    Err(why) => {
+                        log::warn!("Could not get the certainty of point {id}: {why:?}");
+                        None
+                    }
+                };
+                fetched.push(certainty);
+            }
+            certainties.set(fetched);
+            Ok::<_, ()>(())
+        }
+    });
+    {
+        let fetch_certainties = fetch_certainties.clone();
+        let has_next = !next_batch.is_empty();
+        let deps = (
+            damages.data.is_some(),
+            certainties.len(),
+            fetch_certainties.loading,
+        );
+        use_effect_with_deps(
+            move |(_, _, loading)| {
+                if has_next && !loading {
+                    fetch_certainties.run();
+                }
+                || ()
+            },
+            deps,
+        );
+    }
+
+    let damages = match (&damages.data, &damages.error) {
+        (Some(damages), _) => damages.clone(),
+        (None, Some(why)) => {
+            return html! {
+                <div class="container-fluid py-3">
+                    <div class="alert alert-danger" role="alert">
+                        {"Cannot fetch damages: "}
+                        <code>{why.to_string()}</code>
+                    </div>
+                </div>
+            }
+        }
+        (None, None) => return spinner(),
+    };
+
+    let mut class_counts: HashMap<&str, usize> = HashMap::new();
+    for damage in damages.iter() {
+        *class_counts
+            .entry(damage_class(&damage.damage_type))
+            .or_default() += 1;
+    }
+    let type_bars = damage_classes()
+        .into_iter()
+        .map(|class| Bar {
+            label: class.label.to_string(),
+            value: class_counts.get(class.key).copied().unwrap_or(0),
+            color: icon_color(icon_name(&class.example)),
+        })
+        .collect::<Vec<_>>();
+
+    let grid = AreaGrid::new(&damages);
+    let heat_cells = grid
+        .cells
+        .iter()
+        .map(|(&(row, column), damages)| {
+            let (lat, lon) = grid.cell_center((row, column));
+            HeatCell {
+                row,
+                column,
+                value: damages.len(),
+                title: format!("{lat:.3}, {lon:.3}: {} damages", damages.len()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut worst = grid.cells.iter().collect::<Vec<_>>();
+    worst.sort_by_key(|&(cell, damages)| (std::cmp::Reverse(damages.len()), *cell));
+    let worst_rows = worst
+        .into_iter()
+        .take(WORST_AREAS)
+        .enumerate()
+        .map(|(place, (&cell, damages))| {
+            let (lat, lon) = grid.cell_center(cell);
+            let query = Permalink {
+                lat: Some(lat),
+                lon: Some(lon),
+                zoom: Some(AREA_ZOOM),
+                ..Default::default()
+            };
+            html! {
+                <tr>
+                    <td>{place + 1}</td>
+                    <td>
+                        <Link<Route, Permalink> to={Route::Map} query={Some(query)}>
+                            {format!("{lat:.3}, {lon:.3}")}
+                        </Link<Route, Permalink>>
+                    </td>
+                    <td>{damages.len()}</td>
+                    <td>{most_common(damages)}</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    let known: Vec<f64> = certainties.iter().flatten().copied().collect();
+    let certainty_chart = if known.is_empty() && certainties.len() < damages.len() {
+        spinner()
+    } else if known.is_empty() {
+        html!(<p class="mb-0">{"No certainty could be fetched."}</p>)
+    } else {
+        let mut bins = vec![0; CERTAINTY_BINS];
+        for certainty in &known {
+            let bin = (certainty * CERTAINTY_BINS as f64) as usize;
+            bins[bin.min(CERTAINTY_BINS - 1)] += 1;
+        }
+        let labels: Vec<_> = (0..CERTAINTY_BINS)
+            .map(|bin| format!("{}%", bin * 100 / CERTAINTY_BINS))
+            .collect();
+        let progress = if certainties.len() < damages.len() {
+            format!(
+                "Top certainty of the first {} of {} damages, fetched one by one; the others are on their way.",
+                known.len(),
+                damages.len()
+            )
+        } else {
+            format!("Top certainty of {} damages.", known.len())
+        };
+        html! {
+            <>
+                <Histogram {bins} {labels} />
+                <div class="form-text">{progress}{" Each column starts at its label."}</div>
+            </>
+        }
+    };
+
+    html! {
+        <div class="container-fluid py-3" style="overflow: auto;">
+            <p>{format!("{} damages in all.", damages.len())}</p>
+            <div class="row g-3">
+                {card("Damages by type", html!(<BarChart bars={type_bars} />))}
+                {card("Certainty", certainty_chart)}
+                {card("Damages by area", html! {
+                    <>
+                        <HeatGrid rows={grid.rows} columns={grid.columns} cells={heat_cells} />
+                        <div class="form-text">
+                            {format!("Each square is about {:.1} km across, with north up; hover it for its count.", grid.cell_height * KM_PER_DEGREE)}
+                        </div>
+                    </>
+                })}
+                {card("Worst areas", html! {
+                    <table class="table table-sm">
+                        <thead>
+                            <tr><th>{"#"}</th><th>{"Area center"}</th><th>{"Damages"}</th><th>{"Most common type"}</th></tr>
+                        </thead>
+                        <tbody>{worst_rows}</tbody>
+                    </table>
+                })}
+                {card("Damages over time", html! {
+                    <p class="mb-0">{"Damages have no detection date in the backend yet, so they cannot be charted over time."}</p>
+                })}
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,164 @@
+//! Small SVG charts, drawn by Yew like the rest of the page.
+
+use yew::prelude::*;
+
+/// Height of one bar in bar charts, in SVG units
+const BAR_HEIGHT: f64 = 24.0;
+/// Room left of the bars for their labels
+const LABEL_WIDTH: f64 = 240.0;
+/// Room right of the bars for their values
+const VALUE_WIDTH: f64 = 60.0;
+const CHART_WIDTH: f64 = 640.0;
+/// Height of the column area in histograms
+const COLUMN_AREA_HEIGHT: f64 = 200.0;
+/// Side of one cell of heat grids
+const CELL_SIZE: f64 = 12.0;
+
+#[derive(Clone, PartialEq)]
+pub struct Bar {
+    pub label: String,
+    pub value: usize,
+    /// CSS color of the bar
+    pub color: &'static str,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct BarChartProps {
+    pub bars: Vec<Bar>,
+}
+
+/// Horizontal bars, one per row, scaled to the biggest value
+#[function_component]
+pub fn BarChart(props: &BarChartProps) -> Html {
+    let max = props
+        .bars
+        .iter()
+        .map(|bar| bar.value)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let bar_space = CHART_WIDTH - LABEL_WIDTH - VALUE_WIDTH;
+    let height = BAR_HEIGHT * props.bars.len() as f64;
+
+    let rows = props
+        .bars
+        .iter()
+        .enumerate()
+        .map(|(i, bar)| {
+            let y = i as f64 * BAR_HEIGHT;
+            let width = bar.value as f64 / max as f64 * bar_space;
+            let text_y = y + BAR_HEIGHT * 0.7;
+            html! {
+                <g>
+                    <text x={(LABEL_WIDTH - 8.0).to_string()} y={text_y.to_string()} text-anchor="end" fill="currentColor" font-size="13">{&bar.label}</text>
+                    <rect x={LABEL_WIDTH.to_string()} y={(y + 3.0).to_string()} width={width.to_string()} height={(BAR_HEIGHT - 6.0).to_string()} fill={bar.color} />
+                    <text x={(LABEL_WIDTH + width + 6.0).to_string()} y={text_y.to_string()} fill="currentColor" font-size="13">{bar.value}</text>
+                </g>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <svg viewBox={format!("0 0 {CHART_WIDTH} {height}")} style="width: 100%;">
+            {rows}
+        </svg>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct HistogramProps {
+    /// Count in each bin, from the lowest
+    pub bins: Vec<usize>,
+    /// Label under each bin
+    pub labels: Vec<String>,
+}
+
+/// Vertical columns, one per bin, scaled to the biggest count
+#[function_component]
+pub fn Histogram(props: &HistogramProps) -> Html {
+    let max = props.bins.iter().copied().max().unwrap_or(0).max(1);
+    let column_width = CHART_WIDTH / props.bins.len().max(1) as f64;
+    // Room above the columns for counts and below them for labels
+    let top = 20.0;
+    let height = top + COLUMN_AREA_HEIGHT + 24.0;
+
+    let columns = props
+        .bins
+        .iter()
+        .zip(&props.labels)
+        .enumerate()
+        .map(|(i, (count, label))| {
+            let x = i as f64 * column_width;
+            let column_height = *count as f64 / max as f64 * COLUMN_AREA_HEIGHT;
+            let y = top + COLUMN_AREA_HEIGHT - column_height;
+            let middle = (x + column_width / 2.0).to_string();
+            html! {
+                <g>
+                    <rect x={(x + 2.0).to_string()} y={y.to_string()} width={(column_width - 4.0).to_string()} height={column_height.to_string()} fill="var(--bs-info)" />
+                    <text x={middle.clone()} y={(y - 4.0).to_string()} text-anchor="middle" fill="currentColor" font-size="12">{count}</text>
+                    <text x={middle} y={(height - 6.0).to_string()} text-anchor="middle" fill="currentColor" font-size="12">{label}</text>
+                </g>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <svg viewBox={format!("0 0 {CHART_WIDTH} {height}")} style="width: 100%;">
+            {columns}
+        </svg>
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct HeatCell {
+    /// Counted from the top
+    pub row: usize,
+    /// Counted from the left
+    pub column: usize,
+    pub value: usize,
+    /// Shown on hover
+    pub title: String,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct HeatGridProps {
+    pub rows: usize,
+    pub columns: usize,
+    /// Cells with a value; the others are left empty
+    pub cells: Vec<HeatCell>,
+}
+
+/// A grid of squares, more opaque for bigger values
+#[function_component]
+pub fn HeatGrid(props: &HeatGridProps) -> Html {
+    let max = props
+        .cells
+        .iter()
+        .map(|cell| cell.value)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let width = props.columns as f64 * CELL_SIZE;
+    let height = props.rows as f64 * CELL_SIZE;
+
+    let cells = props
+        .cells
+        .iter()
+        .map(|cell| {
+            // Keep single damages visible next to the worst cells
+            let opacity = 0.15 + 0.85 * cell.value as f64 / max as f64;
+            html! {
+                <rect x={(cell.column as f64 * CELL_SIZE).to_string()} y={(cell.row as f64 * CELL_SIZE).to_string()} width={CELL_SIZE.to_string()} height={CELL_SIZE.to_string()} fill="var(--bs-danger)" fill-opacity={format!("{opacity:.2}")}>
+                    <title>{&cell.title}</title>
+                </rect>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <svg viewBox={format!("0 0 {width} {height}")} style="width: 100%; max-height: 60vh;">
+            <rect width={width.to_string()} height={height.to_string()} fill="none" stroke="var(--bs-border-color)" />
+            {cells}
+        </svg>
+    }
+}
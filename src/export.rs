@@ -0,0 +1,230 @@
+//! Saving damages into files for other tools, made in the browser.
+
+use common_data::RoadDamage;
+use gloo_timers::callback::Timeout;
+use gloo_utils::document;
+use serde::Serialize;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+use yew::prelude::*;
+
+use crate::damage::{damage_class, damage_label};
+
+/// How long the file stays available for the browser to save, in milliseconds
+const DOWNLOAD_TIMEOUT: u32 = 40_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    GeoJson,
+    Csv,
+    Kml,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] =
+        [ExportFormat::GeoJson, ExportFormat::Csv, ExportFormat::Kml];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::GeoJson => "GeoJSON",
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Kml => "KML",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Kml => "kml",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::GeoJson => "application/geo+json",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Kml => "application/vnd.google-earth.kml+xml",
+        }
+    }
+
+    pub fn serialize(self, damages: &[RoadDamage]) -> String {
+        match self {
+            ExportFormat::GeoJson => to_geojson(damages),
+            ExportFormat::Csv => to_csv(damages),
+            ExportFormat::Kml => to_kml(damages),
+        }
+    }
+
+    /// Offer the damages as a file named `<file_stem>.<extension>`
+    pub fn save(self, file_stem: &str, damages: &[RoadDamage]) {
+        let file_name = format!("{file_stem}.{}", self.extension());
+        let contents = self.serialize(damages);
+        if let Err(why) = download(&file_name, self.content_type(), &contents) {
+            log::error!("Could not save {file_name}: {why:?}");
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FeatureCollection {
+    r#type: &'static str,
+    features: Vec<Feature>,
+}
+
+#[derive(Serialize)]
+struct Feature {
+    r#type: &'static str,
+    id: u64,
+    geometry: Geometry,
+    properties: Properties,
+}
+
+#[derive(Serialize)]
+struct Geometry {
+    r#type: &'static str,
+    /// Longitude first, as GeoJSON has it
+    coordinates: [f64; 2],
+}
+
+#[derive(Serialize)]
+struct Properties {
+    id: u64,
+    damage_class: &'static str,
+    damage_type: &'static str,
+}
+
+fn to_geojson(damages: &[RoadDamage]) -> String {
+    let collection = FeatureCollection {
+        r#type: "FeatureCollection",
+        features: damages
+            .iter()
+            .map(|damage| Feature {
+                r#type: "Feature",
+                id: damage.id,
+                geometry: Geometry {
+                    r#type: "Point",
+                    coordinates: [damage.longitude, damage.latitude],
+                },
+                properties: Properties {
+                    id: damage.id,
+                    damage_class: damage_class(&damage.damage_type),
+                    damage_type: damage_label(&damage.damage_type),
+                },
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&collection).unwrap()
+}
+
+fn to_csv(damages: &[RoadDamage]) -> String {
+    let mut text = "id,damage_class,damage_type,latitude,longitude\n".to_string();
+    for damage in damages {
+        // Labels have no quotes, but may have commas some day
+        text.push_str(&format!(
+            "{},{},\"{}\",{},{}\n",
+            damage.id,
+            damage_class(&damage.damage_type),
+            damage_label(&damage.damage_type),
+            damage.latitude,
+            damage.longitude
+        ));
+    }
+    text
+}
+
+fn to_kml(damages: &[RoadDamage]) -> String {
+    let mut text = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<kml xmlns="http://www.opengis.net/kml/2.2"><Document><name>Road damages</name>"#,
+        "\n"
+    ));
+    for damage in damages {
+        text.push_str(&format!(
+            "<Placemark><name>Point {}</name><description>{}</description><Point><coordinates>{},{}</coordinates></Point></Placemark>\n",
+            damage.id,
+            escape_xml(damage_label(&damage.damage_type)),
+            damage.longitude,
+            damage.latitude
+        ));
+    }
+    text.push_str("</Document></kml>\n");
+    text
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Offer the text as a file for the browser to save
+pub fn download(file_name: &str, content_type: &str, contents: &str) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&JsValue::from_str(contents));
+    let mut options = BlobPropertyBag::new();
+    options.type_(content_type);
+    let blob = Blob::new_with_str_sequence_and_options(&parts, &options)?;
+
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let anchor: HtmlAnchorElement = document().create_element("a")?.dyn_into()?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+    // The download may only start after the click returns
+    Timeout::new(DOWNLOAD_TIMEOUT, move || {
+        if let Err(why) = Url::revoke_object_url(&url) {
+            log::warn!("Could not release the saved file: {why:?}");
+        }
+    })
+    .forget();
+    Ok(())
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ExportButtonsProps {
+    /// Damages in view that are not filtered out
+    pub in_view: Vec<RoadDamage>,
+    /// All loaded damages that are not filtered out
+    pub loaded: Vec<RoadDamage>,
+}
+
+/// Buttons saving the damages in each format, either those in view or all that were loaded
+#[function_component]
+pub fn ExportButtons(props: &ExportButtonsProps) -> Html {
+    let only_in_view = use_state(|| true);
+
+    let buttons = ExportFormat::ALL
+        .into_iter()
+        .map(|format| {
+            let damages = if *only_in_view {
+                props.in_view.clone()
+            } else {
+                props.loaded.clone()
+            };
+            let onclick = Callback::from(move |e: MouseEvent| {
+                e.prevent_default();
+                format.save("road-damages", &damages);
+            });
+            html!(<button class="btn btn-outline-secondary" {onclick}>{format.name()}</button>)
+        })
+        .collect::<Html>();
+
+    let toggle_scope_cb = {
+        let only_in_view = only_in_view.clone();
+        Callback::from(move |_: Event| only_in_view.set(!*only_in_view))
+    };
+
+    html! {
+        <div class="d-flex align-items-center gap-2">
+            <div class="form-check mb-0">
+                <input class="form-check-input" type="checkbox" id="export-in-view" checked={*only_in_view} onchange={toggle_scope_cb} />
+                <label class="form-check-label" for="export-in-view">{"Only in view"}</label>
+            </div>
+            <div class="btn-group" role="group">
+                <span class="btn btn-outline-secondary disabled">{"Export"}</span>
+                {buttons}
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::{BTreeSet, HashMap};
+
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+use crate::{damage::damage_classes, leaflet::icons::ShowIcon};
+
+#[derive(Properties, PartialEq)]
+pub struct FilterPanelProps {
+    /// Number of damages of each class in view, by class key
+    pub counts: HashMap<&'static str, usize>,
+    /// Keys of the classes not drawn on the map
+    pub hidden: BTreeSet<String>,
+    pub on_toggle: Callback<&'static str>,
+    /// Least top certainty of the damages drawn, from 0 to 1
+    pub min_certainty: f64,
+    pub on_min_certainty: Callback<f64>,
+}
+
+/// Checkbox for each damage class, to show only some of them on the map,
+/// and a slider hiding damages detected with less certainty
+#[function_component]
+pub fn FilterPanel(props: &FilterPanelProps) -> Html {
+    let rows = damage_classes()
+        .into_iter()
+        .map(|class| {
+            let key = class.key;
+            let id = format!("filter-{key}");
+            let count = props.counts.get(key).copied().unwrap_or(0);
+            let onchange = {
+                let on_toggle = props.on_toggle.clone();
+                Callback::from(move |_: Event| on_toggle.emit(key))
+            };
+            html! {
+                <div class="form-check">
+                    <input class="form-check-input" type="checkbox" id={id.clone()} checked={!props.hidden.contains(key)} {onchange} />
+                    <label class="form-check-label" for={id}>
+                        <span style="position: relative; display: inline-block; width: 1.5em; height: 1.5em; vertical-align: middle;">
+                            <ShowIcon damage_type={class.example} />
+                        </span>
+                        {" "}{class.label}
+                        <span class="badge rounded-pill text-bg-secondary ms-2">{count}</span>
+                    </label>
+                </div>
+            }
+        })
+        .collect::<Html>();
+
+    let oninput = {
+        let on_min_certainty = props.on_min_certainty.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            if let Ok(percent) = input.value().parse::<f64>() {
+                on_min_certainty.emit(percent / 100.0);
+            }
+        })
+    };
+    let percent = (props.min_certainty * 100.0).round();
+
+    html! {
+        <div class="card m-3" style="position: absolute; bottom: 0; left: 0; z-index: 1000;">
+            <div class="card-body py-2">
+                <h6 class="card-title">{"Damage types"}</h6>
+                {rows}
+                <label class="form-label mt-2 mb-0" for="filter-certainty">
+                    {format!("Certainty at least {percent}%")}
+                </label>
+                <input class="form-range" type="range" id="filter-certainty" min="0" max="100" step="5" value={percent.to_string()} {oninput} />
+            </div>
+        </div>
+    }
+}
@@ -1,23 +1,38 @@
+mod cluster;
 pub mod icons;
 mod layers;
+mod overlays;
+mod selection;
 
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use common_data::{DamageType, RoadDamage};
+use common_data::RoadDamage;
 use gloo_utils::document;
 use leaflet::{LatLng, Map, Marker};
 use wasm_bindgen::{prelude::*, JsCast};
 use web_sys::{HtmlElement, Node};
 use yew::prelude::*;
 use yew_hooks::{use_async, use_debounce, use_is_first_mount};
+use yew_router::prelude::*;
 
 use crate::{
+    config::AppConfig,
+    damage::damage_class,
+    damage_table::DamageTable,
+    export::ExportButtons,
+    filter_panel::FilterPanel,
     leaflet::{
-        icons::IconGenerator,
-        layers::{get_default_layer, get_matrix_layer, get_transport_layer},
+        cluster::Point,
+        icons::{cluster_icon, icon_name, IconGenerator, ICON_NAMES},
+        layers::tile_layer,
+        overlays::OverlayPanel,
+        selection::SelectionTool,
     },
+    permalink::{MapView, Permalink, Route},
     point_display::PointDisplay,
-    SERVER_ADDR,
 };
 
 #[derive(PartialEq, Properties, Clone)]
@@ -28,6 +43,14 @@ pub struct Props {
     pub style: AttrValue,
 }
 
+/// Loaded damages by id, with their marker and position
+type Markers = HashMap<u64, (RoadDamage, Rc<Marker>, (f64, f64))>;
+/// Top certainty of loaded damages by id, `None` where it could not be fetched
+type Certainties = HashMap<u64, Option<f64>>;
+
+/// Number of damages whose certainty is fetched at a time, one request each
+const CERTAINTY_BATCH: usize = 50;
+
 #[wasm_bindgen::prelude::wasm_bindgen]
 extern "C" {
     #[derive(Debug)]
@@ -41,24 +64,94 @@ extern "C" {
 
     #[wasm_bindgen(method, js_class = "TileLayer")]
     pub fn remove(this: &MyTileLayer);
+
+    /// A marker, seen as something that can be faded
+    type FadingMarker;
+
+    #[wasm_bindgen(method)]
+    fn setOpacity(this: &FadingMarker, opacity: f64);
 }
 
 #[function_component]
 pub fn MapComponent(props: &Props) -> Html {
+    let config = use_context::<Rc<AppConfig>>().expect("App provides the config");
     let leaflet_box = use_state(|| Option::<Rc<Map>>::None);
     let container_box = use_state(|| None);
     let current_layer = use_state(|| None);
-    enum LayerStyle {
-        Default,
-        TransportDark,
-        Matrix,
-    }
-    let current_layer_style = use_state(|| LayerStyle::Default);
+    // Index into the config's layers
+    let current_layer_style = use_state(|| 0);
+
+    let markers: UseStateHandle<Markers> = use_state(HashMap::new);
 
-    let markers: UseStateHandle<HashMap<u64, (RoadDamage, Rc<Marker>, (f64, f64), bool)>> =
-        use_state(HashMap::new);
+    let clicked_point_info: UseStateHandle<Option<RoadDamage>> = use_state(|| Option::None);
 
-    let clicked_point_info = use_state(|| Option::None);
+    // What the page was opened with; only read when the map is created
+    let location = use_location();
+    let permalink = use_state(|| {
+        location
+            .and_then(|location| location.query::<Permalink>().ok())
+            .unwrap_or_default()
+    });
+    let map_view = use_state(|| Option::<MapView>::None);
+    // The point from the link, selected once its marker has been loaded
+    let pending_point = use_state(|| permalink.point);
+    let hidden_classes = use_state(|| permalink.hidden_classes());
+    let certainties: UseStateHandle<Certainties> = use_state(HashMap::new);
+    // From 0 to 1, like the certainties
+    let min_certainty = use_state(|| 0.0);
+    let show_table = use_state(|| false);
+
+    {
+        let pending_point = pending_point.clone();
+        let clicked_point_info = clicked_point_info.clone();
+        let markers = markers.clone();
+        let deps = (
+            markers.len(),
+            clicked_point_info.as_ref().map(|damage| damage.id),
+        );
+        use_effect_with_deps(
+            move |(_, clicked_id)| {
+                if clicked_id.is_some() {
+                    // Another point was picked in the meantime
+                    if pending_point.is_some() {
+                        pending_point.set(None);
+                    }
+                } else if let Some(id) = *pending_point {
+                    if let Some((damage, ..)) = markers.get(&id) {
+                        clicked_point_info.set(Some(damage.clone()));
+                        pending_point.set(None);
+                    }
+                }
+                || ()
+            },
+            deps,
+        );
+    }
+
+    // Keep the URL in sync, without adding to the history on every move
+    {
+        let navigator = use_navigator();
+        let layer = config
+            .layers
+            .get(*current_layer_style)
+            .map(|layer_config| layer_config.name.clone());
+        let point = clicked_point_info
+            .as_ref()
+            .map(|damage| damage.id)
+            .or(*pending_point);
+        use_effect_with_deps(
+            move |(view, layer, point, hidden)| {
+                if let (Some(navigator), Some(view)) = (navigator, view) {
+                    let permalink = Permalink::new(*view, layer.clone(), *point, hidden);
+                    if let Err(why) = navigator.replace_with_query(&Route::Map, &permalink) {
+                        log::warn!("Could not update the URL: {why}");
+                    }
+                }
+                || ()
+            },
+            (*map_view, layer, point, (*hidden_classes).clone()),
+        );
+    }
 
     // For ensuring that the map container is always the correct size,
     // as well as for invalidating its initial size of zero (before the element is drawn to the screen)
@@ -78,6 +171,7 @@ pub fn MapComponent(props: &Props) -> Html {
         let leaflet = leaflet_box.clone();
         let old_markers = markers.clone();
         let clicked_point_info = clicked_point_info.clone();
+        let backend_url = config.backend_url.clone();
         async move {
             if let Some(leaflet) = leaflet.as_ref() {
                 log::info!("Starting recalculating markers for area!");
@@ -91,7 +185,7 @@ pub fn MapComponent(props: &Props) -> Html {
                             p2: (sw.lng(), sw.lat()),
                         };
 
-                        frontend_requests::get_points_in_rect(SERVER_ADDR, bounds).await?
+                        frontend_requests::get_points_in_rect(&backend_url, bounds).await?
                     };
 
                     log::info!("Starting building markers");
@@ -107,18 +201,7 @@ pub fn MapComponent(props: &Props) -> Html {
                         let pos_raw = (damage.latitude, damage.longitude);
                         let marker = Marker::new(&pos);
 
-                        let icon = match damage.damage_type {
-                            DamageType::Alligator_crack
-                            | DamageType::Linear_longitudinal_crack
-                            | DamageType::Linear_lateral_crack => gen.crack(),
-                            DamageType::White_line_blur | DamageType::Cross_walk_blur => {
-                                gen.patch()
-                            }
-                            DamageType::Rutting_bump_pothole_separation
-                            | DamageType::Utility_hole_maintenance_hatch => gen.hole(),
-                            DamageType::Repair | _ => gen.other(),
-                        };
-
+                        let icon = gen.for_damage(&damage.damage_type);
                         marker.setIcon(&icon);
 
                         let click_handler: Closure<dyn FnMut(leaflet::MouseEvent) -> ()> =
@@ -132,8 +215,8 @@ pub fn MapComponent(props: &Props) -> Html {
 
                         marker.on("click", &click_handler.into_js_value());
 
-                        marker.addTo(&leaflet);
-                        new_markers.insert(damage.id, (damage, Rc::new(marker), pos_raw, true));
+                        // Put on the map when the markers are clustered
+                        new_markers.insert(damage.id, (damage, Rc::new(marker), pos_raw));
                     }
                     new_markers
                 };
@@ -159,6 +242,96 @@ pub fn MapComponent(props: &Props) -> Html {
 
     let perform_bbox_fetch = use_debounce(move || perform_bbox_fetch.run(), 200);
 
+    // Certainty is only known point by point, so it is fetched for the damages in view
+    // as they are loaded, a batch at a time
+    let missing_certainties: Vec<u64> = match leaflet_box.as_ref() {
+        Some(leaflet) => {
+            let bounds = leaflet.getBounds();
+            markers
+                .iter()
+                .filter(|(id, (_damage, _marker, pos))| {
+                    !certainties.contains_key(id) && bounds.contains(&LatLng::new(pos.0, pos.1))
+                })
+                .map(|(id, _)| *id)
+                .take(CERTAINTY_BATCH)
+                .collect()
+        }
+        None => vec![],
+    };
+    let fetch_certainties = use_async({
+        let certainties = certainties.clone();
+        let backend_url = config.backend_url.clone();
+        let missing = missing_certainties.clone();
+        async move {
+            let mut known = (*certainties).clone();
+            for id in missing {
+                let certainty = match frontend_requests::get_info_by_id(&backend_url, id).await {
+                    Ok(info) => Some(info.top_certainty),
+                    Err(why) => {
+                        log::warn!("Could not get the certainty of point {id}: {why:?}");
+                        None
+                    }
+                };
+                known.insert(id, certainty);
+            }
+            certainties.set(known);
+            Ok::<_, ()>(())
+        }
+    });
+    {
+        let fetch_certainties = fetch_certainties.clone();
+        let has_missing = !missing_certainties.is_empty();
+        let deps = (
+            *map_view,
+            markers.len(),
+            certainties.len(),
+            fetch_certainties.loading,
+        );
+        use_effect_with_deps(
+            move |(_, _, _, loading)| {
+                if has_missing && !loading {
+                    fetch_certainties.run();
+                }
+                || ()
+            },
+            deps,
+        );
+    }
+
+    // Markers close to each other at the current zoom are shown as one cluster
+    {
+        let leaflet_box = leaflet_box.clone();
+        let markers = markers.clone();
+        let certainties = certainties.clone();
+        let drawn = use_mut_ref(DrawnMarkers::default);
+        let deps = (
+            *map_view,
+            markers.len(),
+            (*hidden_classes).clone(),
+            certainties.len(),
+            *min_certainty,
+        );
+        use_effect_with_deps(
+            move |(_, _, hidden, _, min_certainty)| {
+                if let Some(leaflet) = leaflet_box.as_ref() {
+                    let shown = |damage: &RoadDamage| {
+                        !hidden.contains(damage_class(&damage.damage_type))
+                            && is_certain_enough(&certainties, damage.id, *min_certainty)
+                    };
+                    draw_clusters(
+                        leaflet,
+                        &markers,
+                        &certainties,
+                        shown,
+                        &mut drawn.borrow_mut(),
+                    );
+                }
+                || ()
+            },
+            deps,
+        );
+    }
+
     let (leaflet, container) = if use_is_first_mount() {
         // Initialize the target HTML element
         let container = document().create_element("div").unwrap();
@@ -166,69 +339,47 @@ pub fn MapComponent(props: &Props) -> Html {
         container.set_class_name("map");
         let leaflet_map = Map::new_with_element(&container, &JsValue::NULL);
 
-        leaflet_map.setView(&LatLng::new(55.34, 37.78), 11.0);
-
-        let layer = get_default_layer();
-        layer.addTo(&leaflet_map);
-        current_layer_style.set(LayerStyle::Default);
-        current_layer.set(Some(layer));
+        let view = permalink.view().unwrap_or(MapView {
+            lat: config.initial_view.lat,
+            lon: config.initial_view.lon,
+            zoom: config.initial_view.zoom,
+        });
+        leaflet_map.setView(&LatLng::new(view.lat, view.lon), view.zoom);
+        map_view.set(Some(view));
+
+        // The linked style, if it is still offered
+        let layer_index = permalink
+            .layer
+            .as_ref()
+            .and_then(|name| {
+                config
+                    .layers
+                    .iter()
+                    .position(|layer_config| &layer_config.name == name)
+            })
+            .unwrap_or(0);
+        if let Some(layer_config) = config.layers.get(layer_index) {
+            let layer = tile_layer(&config.tile_server_url, layer_config);
+            layer.addTo(&leaflet_map);
+            current_layer_style.set(layer_index);
+            current_layer.set(Some(layer));
+        }
 
         container_box.set(Some(container.clone()));
         let leaflet_map = Rc::new(leaflet_map);
 
-        let move_handler: Closure<dyn FnMut(leaflet::MouseEvent) -> ()> = Closure::new({
-            let leaflet = leaflet_map.clone();
-            let markers = markers.clone();
-
-            move |_e| {
-                //e.originalEvent().prevent_default();
-                let bounds = leaflet.getBounds();
-                log::info!("Moving map: current bounds are {:?}", bounds);
-
-                let mut markers_value = (*markers).clone();
-
-                let mut to_add = vec![];
-                let mut to_remove = vec![];
-                for (idx, (_damage, _marker, pos, is_present)) in markers_value.iter() {
-                    let is_visible = bounds.contains(&LatLng::new(pos.0, pos.1));
-                    match (is_visible, is_present) {
-                        (true, false) => {
-                            // Is not on map, but should be
-                            to_add.push(*idx);
-                        }
-                        (false, true) => {
-                            // Is on map, but shouldn't be
-                            to_remove.push(*idx);
-                        }
-                        _ => {}
-                    }
-                }
-
-                // Apply changes
-                for idx in to_add {
-                    let (_damage, marker, _pos, is_present) = markers_value.get_mut(&idx).unwrap();
-                    marker.addTo(&leaflet);
-                    log::info!("Adding marker {idx} to map");
-                    *is_present = true;
-                }
-                for idx in to_remove {
-                    let (_damage, marker, _pos, is_present) = markers_value.get_mut(&idx).unwrap();
-
-                    marker.remove();
-                    log::info!("Removing marker {idx} from map");
-                    *is_present = false;
-                }
-
-                markers.set(markers_value);
-            }
-        });
-
-        leaflet_map.on("move", &move_handler.into_js_value());
-
         let move_finish_handler: Closure<dyn FnMut(leaflet::MouseEvent) -> ()> = Closure::new({
             let perform_bbox_fetch = perform_bbox_fetch.clone();
+            let leaflet = leaflet_map.clone();
+            let map_view = map_view.clone();
             move |_e| {
                 log::info!("Map drag is complete, querying for new marker state");
+                let center = leaflet.getCenter();
+                map_view.set(Some(MapView {
+                    lat: center.lat(),
+                    lon: center.lng(),
+                    zoom: leaflet.getZoom(),
+                }));
                 perform_bbox_fetch_reset();
                 perform_bbox_fetch.run()
             }
@@ -251,66 +402,46 @@ pub fn MapComponent(props: &Props) -> Html {
         (map, container)
     };
 
-    let set_default_style_cb = {
-        let leaflet = leaflet.clone();
-        let current_layer = current_layer.clone();
-        let current_layer_style = current_layer_style.clone();
-
-        Callback::from(move |e: MouseEvent| {
-            e.prevent_default();
-            let new_layer = get_default_layer();
-            let old_layer = current_layer.as_ref().unwrap(); // current_layer is always set when replaced
-            old_layer.remove();
-            new_layer.addTo(&leaflet);
-            current_layer.set(Some(new_layer));
-            current_layer_style.set(LayerStyle::Default);
-        })
-    };
-    let set_transport_style_cb: Callback<MouseEvent> = {
-        let leaflet = leaflet.clone();
-        let current_layer = current_layer.clone();
-        let current_layer_style = current_layer_style.clone();
-
-        Callback::from(move |e: MouseEvent| {
-            e.prevent_default();
-            let new_layer = get_transport_layer();
-            let old_layer = current_layer.as_ref().unwrap(); // current_layer is always set when replaced
-            old_layer.remove();
-            new_layer.addTo(&leaflet);
-            current_layer.set(Some(new_layer));
-            current_layer_style.set(LayerStyle::TransportDark);
+    let map_style_buttons = config
+        .layers
+        .iter()
+        .enumerate()
+        .map(|(index, layer_config)| {
+            let set_style_cb = {
+                let leaflet = leaflet.clone();
+                let current_layer = current_layer.clone();
+                let current_layer_style = current_layer_style.clone();
+                let tile_server_url = config.tile_server_url.clone();
+                let layer_config = layer_config.clone();
+
+                Callback::from(move |e: MouseEvent| {
+                    e.prevent_default();
+                    let new_layer = tile_layer(&tile_server_url, &layer_config);
+                    // current_layer is set at startup whenever there are layers to choose from
+                    if let Some(old_layer) = current_layer.as_ref() {
+                        old_layer.remove();
+                    }
+                    new_layer.addTo(&leaflet);
+                    current_layer.set(Some(new_layer));
+                    current_layer_style.set(index);
+                })
+            };
+            html! {
+                <button class={classes!("btn", if *current_layer_style == index {"btn-primary"} else {"btn-outline-primary"})} onclick={set_style_cb}>
+                    {layer_config.title.clone()}
+                </button>
+            }
         })
-    };
+        .collect::<Html>();
 
-    let set_matrix_style_cb: Callback<MouseEvent> = {
-        let leaflet = leaflet.clone();
-        let current_layer = current_layer.clone();
-        let current_layer_style = current_layer_style.clone();
+    let toggle_table_cb = {
+        let show_table = show_table.clone();
         Callback::from(move |e: MouseEvent| {
             e.prevent_default();
-            let new_layer = get_matrix_layer();
-            let old_layer = current_layer.as_ref().unwrap(); // current_layer is always set when replaced
-            old_layer.remove();
-            new_layer.addTo(&leaflet);
-            current_layer.set(Some(new_layer));
-            current_layer_style.set(LayerStyle::Matrix);
+            show_table.set(!*show_table);
         })
     };
 
-    let map_style_choice = html! {
-        <div class="btn-group" role="group">
-            <button class={classes!("btn", if matches!(*current_layer_style, LayerStyle::Default) {"btn-primary"} else {"btn-outline-primary"})} onclick={set_default_style_cb}>
-                {"Default"}
-            </button>
-            <button class={classes!("btn", if matches!(*current_layer_style, LayerStyle::TransportDark) {"btn-primary"} else {"btn-outline-primary"})} onclick={set_transport_style_cb}>
-                {"TransportDark"}
-            </button>
-            <button class={classes!("btn", if matches!(*current_layer_style, LayerStyle::Matrix) {"btn-primary"} else {"btn-outline-primary"})} onclick={set_matrix_style_cb}>
-                {"Matrix"}
-            </button>
-        </div>
-    };
-
     let clear_clicked_cb = Callback::from({
         let clicked_point_id = clicked_point_info.clone();
         move |_| {
@@ -318,6 +449,80 @@ pub fn MapComponent(props: &Props) -> Html {
         }
     });
 
+    // Damages of each class in view, for the filter panel,
+    // and those shown, for the table and exports
+    let mut class_counts: HashMap<&'static str, usize> = HashMap::new();
+    let mut damages_in_view = vec![];
+    let mut damages_loaded = vec![];
+    let bounds = leaflet.getBounds();
+    for (damage, _marker, pos) in markers.values() {
+        if !is_certain_enough(&certainties, damage.id, *min_certainty) {
+            continue;
+        }
+        let class = damage_class(&damage.damage_type);
+        let is_visible = bounds.contains(&LatLng::new(pos.0, pos.1));
+        if is_visible {
+            *class_counts.entry(class).or_default() += 1;
+        }
+        if !hidden_classes.contains(class) {
+            damages_loaded.push(damage.clone());
+            if is_visible {
+                damages_in_view.push(damage.clone());
+            }
+        }
+    }
+
+    let select_damage_cb = Callback::from({
+        let leaflet = leaflet.clone();
+        let clicked_point_info = clicked_point_info.clone();
+        move |damage: RoadDamage| {
+            leaflet.setView(
+                &LatLng::new(damage.latitude, damage.longitude),
+                leaflet.getZoom(),
+            );
+            clicked_point_info.set(Some(damage));
+        }
+    });
+    let table = if *show_table {
+        html! {
+            <div class="tableview">
+                <DamageTable damages={damages_in_view.clone()} selected={clicked_point_info.as_ref().map(|damage| damage.id)} on_select={select_damage_cb} />
+            </div>
+        }
+    } else {
+        html!()
+    };
+
+    let toggle_class_cb = Callback::from({
+        let hidden_classes = hidden_classes.clone();
+        move |key: &'static str| {
+            let mut hidden = (*hidden_classes).clone();
+            if !hidden.remove(key) {
+                hidden.insert(key.to_string());
+            }
+            hidden_classes.set(hidden);
+        }
+    });
+
+    let set_min_certainty_cb = Callback::from({
+        let min_certainty = min_certainty.clone();
+        move |value: f64| min_certainty.set(value)
+    });
+
+    let map_style_choice = html! {
+        <div class="d-flex justify-content-between">
+            <div class="btn-group" role="group">
+                {map_style_buttons}
+            </div>
+            <div class="d-flex gap-2">
+                <ExportButtons in_view={damages_in_view} loaded={damages_loaded.clone()} />
+                <button class={classes!("btn", if *show_table {"btn-secondary"} else {"btn-outline-secondary"})} onclick={toggle_table_cb}>
+                    {"Table"}
+                </button>
+            </div>
+        </div>
+    };
+
     // To render the map, need to create VRef to the map's element
     let node: &Node = &container.clone().into();
     let loading = perform_bbox_fetch_loading;
@@ -345,10 +550,15 @@ pub fn MapComponent(props: &Props) -> Html {
                     <div class={classes!("card-body", "map-card-body", loading.then_some("text-warning placeholder"), error.then_some("bg-danger"))} style={&props.style}>
                         {Html::VRef(node.clone())}
                         {error_box}
+                        <FilterPanel counts={class_counts} hidden={(*hidden_classes).clone()} on_toggle={toggle_class_cb} min_certainty={*min_certainty} on_min_certainty={set_min_certainty_cb} />
+                        <OverlayPanel leaflet={leaflet.clone()} />
+                        <SelectionTool leaflet={leaflet.clone()} damages={damages_loaded} certainties={(*certainties).clone()} />
                     </div>
                     {map_style_choice}
                 </div>
 
+                {table}
+
                 // Side element containing info about clicked points
 
                 <PointDisplay leaflet={leaflet.clone()} clicked_point_info={(*clicked_point_info).clone()} {clear_clicked_cb} />
@@ -356,3 +566,105 @@ pub fn MapComponent(props: &Props) -> Html {
         </div>
     }
 }
+
+/// What is on the map: markers of single damages, and cluster markers
+#[derive(Default)]
+struct DrawnMarkers {
+    singles: HashSet<u64>,
+    clusters: Vec<Marker>,
+}
+
+/// Whether the damage is not known to be less certain than `min_certainty`.
+/// Damages whose certainty is not loaded yet are kept.
+fn is_certain_enough(certainties: &Certainties, id: u64, min_certainty: f64) -> bool {
+    !certainties
+        .get(&id)
+        .copied()
+        .flatten()
+        .is_some_and(|certainty| certainty < min_certainty)
+}
+
+/// Markers of less certain damages are fainter, those not known yet are drawn in full
+fn marker_opacity(certainty: Option<f64>) -> f64 {
+    certainty.map_or(1.0, |certainty| 0.3 + 0.7 * certainty.clamp(0.0, 1.0))
+}
+
+fn draw_clusters(
+    leaflet: &Rc<Map>,
+    markers: &Markers,
+    certainties: &Certainties,
+    shown: impl Fn(&RoadDamage) -> bool,
+    drawn: &mut DrawnMarkers,
+) {
+    for marker in drawn.clusters.drain(..) {
+        marker.remove();
+    }
+
+    let bounds = leaflet.getBounds();
+    let points: Vec<_> = markers
+        .iter()
+        .filter(|(_, (damage, _marker, _pos))| shown(damage))
+        .map(|(id, (_damage, _marker, pos))| Point {
+            id: *id,
+            lat: pos.0,
+            lon: pos.1,
+        })
+        .collect();
+
+    let mut singles = HashSet::new();
+    for cluster in cluster::cluster(&points, leaflet.getZoom()) {
+        let pos = LatLng::new(cluster.lat, cluster.lon);
+        if !bounds.contains(&pos) {
+            continue;
+        }
+        if let [id] = cluster.ids[..] {
+            singles.insert(id);
+            continue;
+        }
+
+        let mut counts = [0; ICON_NAMES.len()];
+        for id in &cluster.ids {
+            let name = icon_name(&markers[id].0.damage_type);
+            if let Some(index) = ICON_NAMES.iter().position(|other| *other == name) {
+                counts[index] += 1;
+            }
+        }
+        let marker = Marker::new(&pos);
+        marker.setIcon(&cluster_icon(&counts));
+
+        let click_handler: Closure<dyn FnMut(leaflet::MouseEvent)> = Closure::new({
+            let leaflet = leaflet.clone();
+            move |_e| {
+                leaflet.setView(
+                    &LatLng::new(cluster.lat, cluster.lon),
+                    f64::from(cluster.expansion_zoom),
+                );
+            }
+        });
+        marker.on("click", &click_handler.into_js_value());
+
+        marker.addTo(leaflet);
+        drawn.clusters.push(marker);
+    }
+
+    for id in drawn.singles.difference(&singles) {
+        if let Some((_damage, marker, _pos)) = markers.get(id) {
+            marker.remove();
+        }
+    }
+    for id in singles.difference(&drawn.singles) {
+        if let Some((_damage, marker, _pos)) = markers.get(id) {
+            marker.addTo(leaflet);
+        }
+    }
+    // Certainties keep coming in after the markers are drawn
+    for id in &singles {
+        if let Some((_damage, marker, _pos)) = markers.get(id) {
+            let certainty = certainties.get(id).copied().flatten();
+            marker
+                .unchecked_ref::<FadingMarker>()
+                .setOpacity(marker_opacity(certainty));
+        }
+    }
+    drawn.singles = singles;
+}
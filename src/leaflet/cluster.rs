@@ -0,0 +1,95 @@
+//! Grouping of markers that would overlap at the map's zoom.
+//!
+//! Points are put into a grid of square cells in screen pixels, and the points
+//! of each cell make one cluster at their average position.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Width of a grid cell, about the size of two marker icons
+const CELL_SIZE: f64 = 60.0;
+
+/// From this zoom on, every point is shown on its own
+pub const MAX_CLUSTER_ZOOM: u8 = 18;
+
+/// Size of the world in pixels at zoom 0
+const TILE_SIZE: f64 = 256.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Point {
+    pub id: u64,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cluster {
+    /// Average position of the points
+    pub lat: f64,
+    pub lon: f64,
+    pub ids: Vec<u64>,
+    /// Zoom at which the points stop being in a single cluster
+    pub expansion_zoom: u8,
+}
+
+/// Group the points that are close at `zoom`. Clusters of one point are points shown on their own.
+pub fn cluster(points: &[Point], zoom: f64) -> Vec<Cluster> {
+    let zoom = zoom.floor().max(0.0) as u8;
+    if zoom >= MAX_CLUSTER_ZOOM {
+        return points
+            .iter()
+            .map(|point| Cluster {
+                lat: point.lat,
+                lon: point.lon,
+                ids: vec![point.id],
+                expansion_zoom: zoom,
+            })
+            .collect();
+    }
+
+    let mut clusters = vec![];
+    for cell_points in group_by_cell(points, zoom).into_values() {
+        let count = cell_points.len() as f64;
+        clusters.push(Cluster {
+            lat: cell_points.iter().map(|point| point.lat).sum::<f64>() / count,
+            lon: cell_points.iter().map(|point| point.lon).sum::<f64>() / count,
+            ids: cell_points.iter().map(|point| point.id).collect(),
+            expansion_zoom: expansion_zoom(&cell_points, zoom),
+        });
+    }
+    // Same order on every redraw, so that overlapping clusters do not swap places
+    clusters.sort_by_key(|cluster| cluster.ids.iter().min().copied());
+    clusters
+}
+
+/// The first zoom above `zoom` where the points fall into different cells
+fn expansion_zoom(points: &[Point], zoom: u8) -> u8 {
+    if points.len() < 2 {
+        return zoom;
+    }
+    (zoom + 1..MAX_CLUSTER_ZOOM)
+        .find(|&zoom| group_by_cell(points, zoom).len() > 1)
+        .unwrap_or(MAX_CLUSTER_ZOOM)
+}
+
+fn group_by_cell(points: &[Point], zoom: u8) -> HashMap<(i64, i64), Vec<Point>> {
+    let mut cells: HashMap<(i64, i64), Vec<Point>> = HashMap::new();
+    for point in points {
+        let (x, y) = project(point.lat, point.lon, zoom);
+        let cell = (
+            (x / CELL_SIZE).floor() as i64,
+            (y / CELL_SIZE).floor() as i64,
+        );
+        cells.entry(cell).or_default().push(*point);
+    }
+    cells
+}
+
+/// Web Mercator position in pixels from the top left corner of the world
+fn project(lat: f64, lon: f64, zoom: u8) -> (f64, f64) {
+    let world_size = TILE_SIZE * f64::from(1u32 << zoom);
+    let lat = lat.clamp(-85.05112878, 85.05112878).to_radians();
+    let x = (lon + 180.0) / 360.0 * world_size;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * world_size;
+    (x, y)
+}
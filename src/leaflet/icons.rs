@@ -2,8 +2,16 @@ use std::rc::Rc;
 
 use common_data::DamageType;
 use leaflet::Icon;
+use wasm_bindgen::prelude::*;
 use yew::prelude::*;
 
+#[wasm_bindgen]
+extern "C" {
+    /// Icon made of HTML instead of an image
+    #[wasm_bindgen(js_namespace = L, js_name = divIcon)]
+    fn div_icon(options: &JsValue) -> Icon;
+}
+
 #[derive(serde::Serialize)]
 #[allow(non_snake_case)]
 struct IconOptions {
@@ -12,6 +20,41 @@ struct IconOptions {
     iconAnchor: Vec<u32>,
 }
 
+#[derive(serde::Serialize)]
+#[allow(non_snake_case)]
+struct DivIconOptions {
+    html: String,
+    className: String,
+    iconSize: Vec<u32>,
+}
+
+/// Icon kinds, in the order they are drawn in cluster icons
+pub const ICON_NAMES: [&str; 4] = ["hole", "crack", "patch", "other"];
+
+/// Name of the icon files showing a kind of damage
+pub fn icon_name(damage_type: &DamageType) -> &'static str {
+    match damage_type {
+        DamageType::Alligator_crack
+        | DamageType::Linear_longitudinal_crack
+        | DamageType::Linear_lateral_crack => "crack",
+        DamageType::White_line_blur | DamageType::Cross_walk_blur => "patch",
+        DamageType::Rutting_bump_pothole_separation
+        | DamageType::Utility_hole_maintenance_hatch => "hole",
+        DamageType::Repair => "other",
+        _ => "other",
+    }
+}
+
+/// Color of each icon kind in cluster icons and dashboard charts
+pub fn icon_color(name: &str) -> &'static str {
+    match name {
+        "hole" => "var(--bs-danger)",
+        "crack" => "var(--bs-warning)",
+        "patch" => "var(--bs-info)",
+        _ => "var(--bs-secondary)",
+    }
+}
+
 #[derive(Properties, PartialEq)]
 pub struct ShowIconProps {
     pub damage_type: DamageType,
@@ -19,16 +62,7 @@ pub struct ShowIconProps {
 
 #[function_component]
 pub fn ShowIcon(props: &ShowIconProps) -> Html {
-    let icon_url = match props.damage_type {
-        DamageType::Alligator_crack
-        | DamageType::Linear_longitudinal_crack
-        | DamageType::Linear_lateral_crack => "/crack.svg",
-        DamageType::White_line_blur | DamageType::Cross_walk_blur => "/patch.svg",
-        DamageType::Rutting_bump_pothole_separation
-        | DamageType::Utility_hole_maintenance_hatch => "/hole.svg",
-        DamageType::Repair => "/other.svg",
-        _ => "/other.svg",
-    };
+    let icon_url = format!("/{}.svg", icon_name(&props.damage_type));
 
     html!(
         <img src={icon_url} style="height: 1.5em; position: absolute; top:0; right:0; display:inline;"/>
@@ -79,4 +113,46 @@ pub struct IconGenerator {
 }
 impl IconGenerator {
     icon_generator! {methods; bump; crack; hole; patch; other;}
+
+    pub fn for_damage(&mut self, damage_type: &DamageType) -> Rc<Icon> {
+        match icon_name(damage_type) {
+            "crack" => self.crack(),
+            "patch" => self.patch(),
+            "hole" => self.hole(),
+            _ => self.other(),
+        }
+    }
+}
+
+/// Round icon with the number of damages in a cluster, ringed by the share of each kind.
+/// `counts` has the number of damages of each kind in `ICON_NAMES`.
+pub fn cluster_icon(counts: &[usize; ICON_NAMES.len()]) -> Icon {
+    let total: usize = counts.iter().sum();
+    let mut stops = vec![];
+    let mut start = 0.0;
+    for (name, count) in ICON_NAMES.iter().zip(counts) {
+        if *count == 0 {
+            continue;
+        }
+        let end = start + *count as f64 / total.max(1) as f64 * 100.0;
+        stops.push(format!("{} {start:.1}% {end:.1}%", icon_color(name)));
+        start = end;
+    }
+    // Bigger clusters get bigger icons
+    let size = match total {
+        0..=9 => 36,
+        10..=99 => 44,
+        _ => 52,
+    };
+    let options = DivIconOptions {
+        html: format!(
+            r#"<div class="damage-cluster" style="background: conic-gradient({});"><span>{total}</span></div>"#,
+            stops.join(", ")
+        ),
+        className: "damage-cluster-icon".to_string(),
+        iconSize: vec![size, size],
+    };
+
+    let options = serde_wasm_bindgen::to_value(&options).unwrap();
+    div_icon(&options)
 }
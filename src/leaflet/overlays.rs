@@ -0,0 +1,435 @@
+//! Vector layers from the user's own GeoJSON and KML files, like planned resurfacing areas,
+//! kept for the browser session.
+
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail, Context};
+use gloo_utils::window;
+use leaflet::Map;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map as JsonMap, Value};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{File, FileList, HtmlInputElement};
+use yew::prelude::*;
+
+/// Key of the overlays in the session storage
+const STORAGE_KEY: &str = "overlays";
+
+/// Colors given to overlays in turn
+const COLORS: [&str; 6] = [
+    "#0d6efd", "#d63384", "#20c997", "#fd7e14", "#6f42c1", "#198754",
+];
+
+#[wasm_bindgen]
+extern "C" {
+    #[derive(Debug)]
+    pub type GeoJsonLayer;
+
+    #[wasm_bindgen(js_namespace = L, js_name = geoJSON)]
+    fn geo_json(data: &JsValue, options: &JsValue) -> GeoJsonLayer;
+
+    #[wasm_bindgen(method)]
+    fn addTo(this: &GeoJsonLayer, map: &Map);
+
+    #[wasm_bindgen(method)]
+    fn remove(this: &GeoJsonLayer);
+
+    type FeatureLayer;
+
+    #[wasm_bindgen(method)]
+    fn bindPopup(this: &FeatureLayer, content: &str);
+
+    #[wasm_bindgen(js_namespace = L, js_name = circleMarker)]
+    fn circle_marker(lat_lng: &JsValue, options: &JsValue) -> JsValue;
+}
+
+/// An imported file, as a GeoJSON feature collection
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Overlay {
+    pub name: String,
+    pub data: Value,
+    pub visible: bool,
+}
+
+#[derive(Clone, PartialEq, Default)]
+pub struct Overlays(Vec<Overlay>);
+
+pub enum OverlayAction {
+    Add(Overlay),
+    Toggle(usize),
+    Remove(usize),
+}
+
+impl Reducible for Overlays {
+    type Action = OverlayAction;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        let mut overlays = self.0.clone();
+        match action {
+            OverlayAction::Add(overlay) => overlays.push(overlay),
+            OverlayAction::Toggle(index) => {
+                if let Some(overlay) = overlays.get_mut(index) {
+                    overlay.visible = !overlay.visible;
+                }
+            }
+            OverlayAction::Remove(index) => {
+                if index < overlays.len() {
+                    overlays.remove(index);
+                }
+            }
+        }
+        Rc::new(Overlays(overlays))
+    }
+}
+
+/// Read a GeoJSON or KML file into a feature collection, picking the format by extension
+pub fn parse_overlay(file_name: &str, text: &str) -> anyhow::Result<Value> {
+    let is_kml =
+        file_name.to_ascii_lowercase().ends_with(".kml") || text.trim_start().starts_with('<');
+    if is_kml {
+        parse_kml(text)
+    } else {
+        parse_geojson(text)
+    }
+}
+
+/// Features and bare geometries are wrapped into a collection
+fn parse_geojson(text: &str) -> anyhow::Result<Value> {
+    let value: Value = serde_json::from_str(text).context("Not valid JSON")?;
+    match value.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => Ok(value),
+        Some("Feature") => Ok(json!({ "type": "FeatureCollection", "features": [value] })),
+        Some(
+            "Point" | "MultiPoint" | "LineString" | "MultiLineString" | "Polygon" | "MultiPolygon"
+            | "GeometryCollection",
+        ) => Ok(json!({
+            "type": "FeatureCollection",
+            "features": [{ "type": "Feature", "geometry": value, "properties": {} }],
+        })),
+        _ => bail!("Not GeoJSON: the `type` is missing or unknown"),
+    }
+}
+
+/// Placemarks become features, with their name, description and extended data as properties
+fn parse_kml(text: &str) -> anyhow::Result<Value> {
+    let document = roxmltree::Document::parse(text).context("Not valid XML")?;
+    let features: Vec<Value> = document
+        .descendants()
+        .filter(|node| node.has_tag_name("Placemark"))
+        .filter_map(|placemark| {
+            let geometry = placemark.children().find_map(kml_geometry)?;
+            let mut properties = JsonMap::new();
+            for field in ["name", "description"] {
+                if let Some(text) = child(placemark, field).and_then(|node| node.text()) {
+                    properties.insert(field.to_string(), text.trim().into());
+                }
+            }
+            for data in placemark.descendants() {
+                let value = if data.has_tag_name("Data") {
+                    child(data, "value").and_then(|node| node.text())
+                } else if data.has_tag_name("SimpleData") {
+                    data.text()
+                } else {
+                    continue;
+                };
+                if let (Some(name), Some(value)) = (data.attribute("name"), value) {
+                    properties.insert(name.to_string(), value.trim().into());
+                }
+            }
+            Some(json!({ "type": "Feature", "geometry": geometry, "properties": properties }))
+        })
+        .collect();
+
+    if features.is_empty() {
+        bail!("The KML file has no placemarks with points, lines or polygons");
+    }
+    Ok(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn kml_geometry(node: roxmltree::Node) -> Option<Value> {
+    let coordinates = |node: roxmltree::Node| -> Option<Vec<[f64; 2]>> {
+        let text = child(node, "coordinates")?.text()?;
+        text.split_whitespace()
+            .map(|tuple| {
+                let mut parts = tuple.split(',').map(str::parse::<f64>);
+                match (parts.next(), parts.next()) {
+                    // KML has longitude first, like GeoJSON
+                    (Some(Ok(lon)), Some(Ok(lat))) => Some([lon, lat]),
+                    _ => None,
+                }
+            })
+            .collect()
+    };
+    let ring = |boundary: roxmltree::Node| -> Option<Vec<[f64; 2]>> {
+        coordinates(child(boundary, "LinearRing")?)
+    };
+
+    if node.has_tag_name("Point") {
+        let point = *coordinates(node)?.first()?;
+        Some(json!({ "type": "Point", "coordinates": point }))
+    } else if node.has_tag_name("LineString") || node.has_tag_name("LinearRing") {
+        Some(json!({ "type": "LineString", "coordinates": coordinates(node)? }))
+    } else if node.has_tag_name("Polygon") {
+        let mut rings = vec![ring(child(node, "outerBoundaryIs")?)?];
+        for inner in node
+            .children()
+            .filter(|child| child.has_tag_name("innerBoundaryIs"))
+        {
+            rings.push(ring(inner)?);
+        }
+        Some(json!({ "type": "Polygon", "coordinates": rings }))
+    } else if node.has_tag_name("MultiGeometry") {
+        let geometries: Vec<Value> = node.children().filter_map(kml_geometry).collect();
+        (!geometries.is_empty())
+            .then(|| json!({ "type": "GeometryCollection", "geometries": geometries }))
+    } else {
+        None
+    }
+}
+
+fn load_session() -> Vec<Overlay> {
+    let stored = window()
+        .session_storage()
+        .ok()
+        .flatten()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten());
+    match stored.map(|text| serde_json::from_str(&text)) {
+        Some(Ok(overlays)) => overlays,
+        Some(Err(why)) => {
+            log::warn!("Could not read the overlays of this session: {why}");
+            vec![]
+        }
+        None => vec![],
+    }
+}
+
+fn save_session(overlays: &[Overlay]) {
+    let Ok(Some(storage)) = window().session_storage() else {
+        return;
+    };
+    let text = serde_json::to_string(overlays).unwrap();
+    // Large files may not fit in the storage; they are still shown until the page is closed
+    if let Err(why) = storage.set_item(STORAGE_KEY, &text) {
+        log::warn!("Could not keep the overlays for this session: {why:?}");
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Popup listing the feature's properties
+fn popup_content(feature: &JsValue) -> Option<String> {
+    let feature: Value = serde_wasm_bindgen::from_value(feature.clone()).ok()?;
+    let properties = feature.get("properties")?.as_object()?;
+    if properties.is_empty() {
+        return None;
+    }
+    let rows: String = properties
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            format!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape_html(key),
+                escape_html(&value)
+            )
+        })
+        .collect();
+    Some(format!(
+        r#"<table class="table table-sm mb-0">{rows}</table>"#
+    ))
+}
+
+fn draw_overlay(leaflet: &Map, overlay: &Overlay, color: &str) -> Result<GeoJsonLayer, JsValue> {
+    let data = js_sys::JSON::parse(&overlay.data.to_string())?;
+
+    let style = js_sys::Object::new();
+    js_sys::Reflect::set(&style, &"color".into(), &color.into())?;
+    js_sys::Reflect::set(&style, &"fillColor".into(), &color.into())?;
+    js_sys::Reflect::set(&style, &"radius".into(), &6.into())?;
+
+    let on_each_feature: Closure<dyn FnMut(JsValue, FeatureLayer)> =
+        Closure::new(|feature: JsValue, layer: FeatureLayer| {
+            if let Some(content) = popup_content(&feature) {
+                layer.bindPopup(&content);
+            }
+        });
+    // Points are drawn as circles, so they stand apart from the damage markers
+    let point_to_layer: Closure<dyn FnMut(JsValue, JsValue) -> JsValue> = Closure::new({
+        let style = style.clone();
+        move |_feature: JsValue, lat_lng: JsValue| circle_marker(&lat_lng, &style)
+    });
+
+    let options = js_sys::Object::new();
+    js_sys::Reflect::set(&options, &"style".into(), &style)?;
+    js_sys::Reflect::set(
+        &options,
+        &"onEachFeature".into(),
+        &on_each_feature.into_js_value(),
+    )?;
+    js_sys::Reflect::set(
+        &options,
+        &"pointToLayer".into(),
+        &point_to_layer.into_js_value(),
+    )?;
+
+    let layer = geo_json(&data, &options);
+    layer.addTo(leaflet);
+    Ok(layer)
+}
+
+/// Read the files and add them as overlays, or tell why they could not be
+fn import_files(
+    files: FileList,
+    dispatch: UseReducerDispatcher<Overlays>,
+    error: UseStateHandle<Option<String>>,
+) {
+    let files: Vec<File> = (0..files.length()).filter_map(|i| files.get(i)).collect();
+    yew::platform::spawn_local(async move {
+        let mut errors = vec![];
+        for file in files {
+            let name = file.name();
+            let text = JsFuture::from(file.text())
+                .await
+                .ok()
+                .and_then(|text| text.as_string())
+                .ok_or_else(|| anyhow!("Could not read the file"));
+            match text.and_then(|text| parse_overlay(&name, &text)) {
+                Ok(data) => dispatch.dispatch(OverlayAction::Add(Overlay {
+                    name,
+                    data,
+                    visible: true,
+                })),
+                Err(why) => errors.push(format!("{name}: {why:#}")),
+            }
+        }
+        error.set((!errors.is_empty()).then(|| errors.join("; ")));
+    });
+}
+
+#[derive(Properties)]
+pub struct OverlayPanelProps {
+    pub leaflet: Rc<Map>,
+}
+
+impl PartialEq for OverlayPanelProps {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::<Map>::as_ptr(&self.leaflet) == Rc::<Map>::as_ptr(&other.leaflet)
+    }
+}
+
+/// List of imported overlays, with a file picker that also takes dropped files
+#[function_component]
+pub fn OverlayPanel(props: &OverlayPanelProps) -> Html {
+    let overlays = use_reducer(|| Overlays(load_session()));
+    let error = use_state(|| Option::<String>::None);
+    let drawn = use_mut_ref(Vec::<GeoJsonLayer>::new);
+
+    {
+        let leaflet = props.leaflet.clone();
+        use_effect_with_deps(
+            move |overlays: &Overlays| {
+                let mut drawn = drawn.borrow_mut();
+                for layer in drawn.drain(..) {
+                    layer.remove();
+                }
+                for (index, overlay) in overlays.0.iter().enumerate() {
+                    if !overlay.visible {
+                        continue;
+                    }
+                    match draw_overlay(&leaflet, overlay, COLORS[index % COLORS.len()]) {
+                        Ok(layer) => drawn.push(layer),
+                        Err(why) => log::error!("Could not draw {}: {why:?}", overlay.name),
+                    }
+                }
+                save_session(&overlays.0);
+                || ()
+            },
+            (*overlays).clone(),
+        );
+    }
+
+    let onchange = {
+        let dispatch = overlays.dispatcher();
+        let error = error.clone();
+        Callback::from(move |e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            if let Some(files) = input.files() {
+                import_files(files, dispatch.clone(), error.clone());
+            }
+            // So that the same file can be picked again after removing it
+            input.set_value("");
+        })
+    };
+    let ondragover = Callback::from(|e: DragEvent| e.prevent_default());
+    let ondrop = {
+        let dispatch = overlays.dispatcher();
+        let error = error.clone();
+        Callback::from(move |e: DragEvent| {
+            e.prevent_default();
+            if let Some(files) = e.data_transfer().and_then(|transfer| transfer.files()) {
+                import_files(files, dispatch.clone(), error.clone());
+            }
+        })
+    };
+
+    let rows = overlays
+        .0
+        .iter()
+        .enumerate()
+        .map(|(index, overlay)| {
+            let toggle = {
+                let dispatch = overlays.dispatcher();
+                Callback::from(move |_: Event| dispatch.dispatch(OverlayAction::Toggle(index)))
+            };
+            let remove = {
+                let dispatch = overlays.dispatcher();
+                Callback::from(move |e: MouseEvent| {
+                    e.prevent_default();
+                    dispatch.dispatch(OverlayAction::Remove(index))
+                })
+            };
+            let id = format!("overlay-{index}");
+            html! {
+                <div class="d-flex align-items-center gap-2">
+                    <div class="form-check mb-0">
+                        <input class="form-check-input" type="checkbox" id={id.clone()} checked={overlay.visible} onchange={toggle} />
+                        <label class="form-check-label" for={id} style={format!("color: {};", COLORS[index % COLORS.len()])}>
+                            {overlay.name.clone()}
+                        </label>
+                    </div>
+                    <button type="button" class="btn-close btn-sm ms-auto" onclick={remove}></button>
+                </div>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <div class="card m-3" style="position: absolute; bottom: 0; right: 0; z-index: 1000; max-width: 20em;" {ondragover} {ondrop}>
+            <div class="card-body py-2">
+                <h6 class="card-title">{"Overlays"}</h6>
+                {rows}
+                <input class="form-control form-control-sm mt-2" type="file" accept=".geojson,.json,.kml" multiple=true {onchange} />
+                <div class="form-text">{"GeoJSON or KML, or drop files here"}</div>
+                if let Some(why) = &*error {
+                    <div class="alert alert-danger nuh-uh py-1 px-2 mt-2 mb-0">{why.clone()}</div>
+                }
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,417 @@
+//! Drawing a rectangle or polygon on the map, and summing up the damages inside it.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use common_data::RoadDamage;
+use leaflet::{LatLng, Map};
+use wasm_bindgen::{prelude::*, JsCast};
+use yew::prelude::*;
+
+use super::Certainties;
+use crate::{
+    damage::{damage_class, damage_classes},
+    export::{download, ExportFormat},
+};
+
+/// Mean radius of the Earth, in km
+const EARTH_RADIUS: f64 = 6371.0;
+
+#[wasm_bindgen]
+extern "C" {
+    type Polygon;
+
+    #[wasm_bindgen(js_namespace = L, js_name = polygon)]
+    fn polygon(lat_lngs: &JsValue, options: &JsValue) -> Polygon;
+
+    #[wasm_bindgen(method)]
+    fn addTo(this: &Polygon, map: &Map);
+
+    #[wasm_bindgen(method)]
+    fn remove(this: &Polygon);
+
+    /// The map, seen as something handlers can also be taken off from
+    type Evented;
+
+    #[wasm_bindgen(method)]
+    fn on(this: &Evented, event: &str, callback: &JsValue);
+
+    #[wasm_bindgen(method)]
+    fn off(this: &Evented, event: &str, callback: &JsValue);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Idle,
+    Rectangle,
+    Polygon,
+}
+
+/// Whether the point is inside the polygon, both as `(lat, lon)`
+pub fn contains(polygon: &[(f64, f64)], point: (f64, f64)) -> bool {
+    let (y, x) = point;
+    let mut inside = false;
+    for (i, &(y1, x1)) in polygon.iter().enumerate() {
+        let (y2, x2) = polygon[(i + 1) % polygon.len()];
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Area of the polygon in km², flattening the Earth around the polygon's middle latitude,
+/// which is close enough for areas the size of a city
+pub fn area_km2(polygon: &[(f64, f64)]) -> f64 {
+    if polygon.len() < 3 {
+        return 0.0;
+    }
+    let mid_lat = polygon.iter().map(|(lat, _)| lat).sum::<f64>() / polygon.len() as f64;
+    let project = |&(lat, lon): &(f64, f64)| {
+        (
+            EARTH_RADIUS * lon.to_radians() * mid_lat.to_radians().cos(),
+            EARTH_RADIUS * lat.to_radians(),
+        )
+    };
+    let points: Vec<_> = polygon.iter().map(project).collect();
+    let twice_area: f64 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|((x1, y1), (x2, y2))| x1 * y2 - x2 * y1)
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+/// Damages of a class in the selection
+struct ClassStats {
+    key: &'static str,
+    label: &'static str,
+    count: usize,
+    certainty: MeanCertainty,
+}
+
+/// Mean top certainty of the damages whose certainty has been fetched
+#[derive(Clone, Copy)]
+struct MeanCertainty {
+    /// Number of damages with a known certainty
+    known: usize,
+    /// From 0 to 1, `None` without any known certainty
+    mean: Option<f64>,
+}
+
+impl MeanCertainty {
+    fn new<'a>(
+        damages: impl IntoIterator<Item = &'a RoadDamage>,
+        certainties: &Certainties,
+    ) -> Self {
+        let known: Vec<f64> = damages
+            .into_iter()
+            .filter_map(|damage| certainties.get(&damage.id).copied().flatten())
+            .collect();
+        let mean = (!known.is_empty()).then(|| known.iter().sum::<f64>() / known.len() as f64);
+        MeanCertainty {
+            known: known.len(),
+            mean,
+        }
+    }
+
+    fn percent(self) -> String {
+        self.mean.map_or("unknown".to_string(), |mean| {
+            format!("{:.1}%", mean * 100.0)
+        })
+    }
+}
+
+/// Stats of each class, in the order of `damage_classes`
+fn class_stats(damages: &[RoadDamage], certainties: &Certainties) -> Vec<ClassStats> {
+    let mut by_class: HashMap<&str, Vec<&RoadDamage>> = HashMap::new();
+    for damage in damages {
+        by_class
+            .entry(damage_class(&damage.damage_type))
+            .or_default()
+            .push(damage);
+    }
+    damage_classes()
+        .into_iter()
+        .map(|class| {
+            let damages = by_class.get(class.key).map_or(&[][..], Vec::as_slice);
+            ClassStats {
+                key: class.key,
+                label: class.label,
+                count: damages.len(),
+                certainty: MeanCertainty::new(damages.iter().copied(), certainties),
+            }
+        })
+        .collect()
+}
+
+/// Road length is not known to the map, so density is counted per km² of the selected area
+/// in place of per km of road, as the column name says
+fn summary_csv(damages: &[RoadDamage], certainties: &Certainties, area: f64) -> String {
+    let mut text =
+        "damage_class,damage_type,count,certainty_known,mean_certainty,per_km2_of_area\n"
+            .to_string();
+    let mut push_row = |key: &str, label: &str, count: usize, certainty: MeanCertainty| {
+        text.push_str(&format!(
+            "{key},\"{label}\",{count},{},{},{:.2}\n",
+            certainty.known,
+            certainty
+                .mean
+                .map_or(String::new(), |mean| format!("{mean:.4}")),
+            count as f64 / area.max(f64::EPSILON)
+        ));
+    };
+    for stats in class_stats(damages, certainties) {
+        push_row(stats.key, stats.label, stats.count, stats.certainty);
+    }
+    push_row(
+        "all",
+        "All",
+        damages.len(),
+        MeanCertainty::new(damages, certainties),
+    );
+    text
+}
+
+fn draw_shape(leaflet: &Map, vertices: &[(f64, f64)], closed: bool) -> Polygon {
+    let lat_lngs: js_sys::Array = vertices
+        .iter()
+        .map(|&(lat, lon)| JsValue::from(LatLng::new(lat, lon)))
+        .collect();
+    let options = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&options, &"color".into(), &"#ffc107".into());
+    if !closed {
+        let _ = js_sys::Reflect::set(&options, &"dashArray".into(), &"6 6".into());
+    }
+    let shape = polygon(&lat_lngs, &options);
+    shape.addTo(leaflet);
+    shape
+}
+
+#[derive(Properties)]
+pub struct SelectionToolProps {
+    pub leaflet: Rc<Map>,
+    /// Loaded damages that are not filtered out
+    pub damages: Vec<RoadDamage>,
+    /// Certainties fetched so far, only for damages that have been in view
+    pub certainties: Certainties,
+}
+
+impl PartialEq for SelectionToolProps {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::<Map>::as_ptr(&self.leaflet) == Rc::<Map>::as_ptr(&other.leaflet)
+            && self.damages == other.damages
+            && self.certainties == other.certainties
+    }
+}
+
+/// Buttons to draw an area, and the statistics of the damages in it
+#[function_component]
+pub fn SelectionTool(props: &SelectionToolProps) -> Html {
+    let mode = use_state(|| Mode::Idle);
+    // Corners as `(lat, lon)`; the shape is complete once `closed`
+    let vertices = use_state(Vec::<(f64, f64)>::new);
+    let closed = use_state(|| false);
+
+    // Map clicks add corners while drawing
+    {
+        let leaflet = props.leaflet.clone();
+        let handler = use_mut_ref(|| Option::<JsValue>::None);
+        let deps = (*mode, (*vertices).clone());
+        let mode = mode.clone();
+        let vertices = vertices.clone();
+        let closed = closed.clone();
+        use_effect_with_deps(
+            move |(current_mode, current_vertices)| {
+                let current_mode = *current_mode;
+                let current_vertices = current_vertices.clone();
+                if current_mode != Mode::Idle {
+                    let click: Closure<dyn FnMut(JsValue)> = Closure::new(move |e: JsValue| {
+                        let Ok(lat_lng) = js_sys::Reflect::get(&e, &"latlng".into()) else {
+                            return;
+                        };
+                        let lat_lng: LatLng = lat_lng.unchecked_into();
+                        let mut new_vertices = current_vertices.clone();
+                        new_vertices.push((lat_lng.lat(), lat_lng.lng()));
+                        if current_mode == Mode::Rectangle && new_vertices.len() == 2 {
+                            let [(lat1, lon1), (lat2, lon2)] = [new_vertices[0], new_vertices[1]];
+                            new_vertices =
+                                vec![(lat1, lon1), (lat1, lon2), (lat2, lon2), (lat2, lon1)];
+                            closed.set(true);
+                            mode.set(Mode::Idle);
+                        }
+                        vertices.set(new_vertices);
+                    });
+                    let click = click.into_js_value();
+                    leaflet.unchecked_ref::<Evented>().on("click", &click);
+                    *handler.borrow_mut() = Some(click);
+                }
+                move || {
+                    if let Some(click) = handler.borrow_mut().take() {
+                        leaflet.unchecked_ref::<Evented>().off("click", &click);
+                    }
+                }
+            },
+            deps,
+        );
+    }
+
+    // The shape follows the corners
+    {
+        let leaflet = props.leaflet.clone();
+        let shape: Rc<RefCell<Option<Polygon>>> = use_mut_ref(|| None);
+        use_effect_with_deps(
+            move |(vertices, closed)| {
+                if let Some(old_shape) = shape.borrow_mut().take() {
+                    old_shape.remove();
+                }
+                if !vertices.is_empty() {
+                    *shape.borrow_mut() = Some(draw_shape(&leaflet, vertices, *closed));
+                }
+                move || {
+                    if let Some(old_shape) = shape.borrow_mut().take() {
+                        old_shape.remove();
+                    }
+                }
+            },
+            ((*vertices).clone(), *closed),
+        );
+    }
+
+    let start = |new_mode: Mode| {
+        let mode = mode.clone();
+        let vertices = vertices.clone();
+        let closed = closed.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            vertices.set(vec![]);
+            closed.set(false);
+            mode.set(new_mode);
+        })
+    };
+    let finish = {
+        let mode = mode.clone();
+        let closed = closed.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            closed.set(true);
+            mode.set(Mode::Idle);
+        })
+    };
+    let clear = {
+        let mode = mode.clone();
+        let vertices = vertices.clone();
+        let closed = closed.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            vertices.set(vec![]);
+            closed.set(false);
+            mode.set(Mode::Idle);
+        })
+    };
+
+    let hint = match *mode {
+        Mode::Rectangle => html!(<div class="form-text">{"Click two opposite corners"}</div>),
+        Mode::Polygon => html!(<div class="form-text">{"Click the corners, then Finish"}</div>),
+        Mode::Idle => html!(),
+    };
+
+    let results = if *closed && vertices.len() >= 3 {
+        let inside: Vec<RoadDamage> = props
+            .damages
+            .iter()
+            .filter(|damage| contains(&vertices, (damage.latitude, damage.longitude)))
+            .cloned()
+            .collect();
+        let area = area_km2(&vertices);
+        let density = |count: usize| format!("{:.1}", count as f64 / area.max(f64::EPSILON));
+
+        let rows = class_stats(&inside, &props.certainties)
+            .into_iter()
+            .filter(|stats| stats.count > 0)
+            .map(|stats| {
+                html! {
+                    <tr>
+                        <td>{stats.label}</td>
+                        <td>{stats.count}</td>
+                        <td>{stats.certainty.percent()}</td>
+                        <td>{density(stats.count)}</td>
+                    </tr>
+                }
+            })
+            .collect::<Html>();
+        let certainty = MeanCertainty::new(&inside, &props.certainties);
+
+        let export_buttons = ExportFormat::ALL
+            .into_iter()
+            .map(|format| {
+                let inside = inside.clone();
+                let onclick = Callback::from(move |e: MouseEvent| {
+                    e.prevent_default();
+                    format.save("selected-damages", &inside);
+                });
+                html!(<button class="btn btn-sm btn-outline-secondary" {onclick}>{format.name()}</button>)
+            })
+            .collect::<Html>();
+        let save_summary = {
+            let summary = summary_csv(&inside, &props.certainties, area);
+            Callback::from(move |e: MouseEvent| {
+                e.prevent_default();
+                if let Err(why) = download("selection-summary.csv", "text/csv", &summary) {
+                    log::error!("Could not save the summary: {why:?}");
+                }
+            })
+        };
+
+        html! {
+            <>
+                <p class="mb-1 mt-2">
+                    {format!("{} damages in {:.3} km², mean certainty {} over the {} with a known one", inside.len(), area, certainty.percent(), certainty.known)}
+                </p>
+                <table class="table table-sm mb-1">
+                    <thead><tr><th>{"Type"}</th><th>{"Count"}</th><th>{"Mean certainty"}</th><th>{"Per km² of area*"}</th></tr></thead>
+                    <tbody>{rows}</tbody>
+                </table>
+                <div class="form-text mb-1">
+                    {"Certainty is fetched for damages once they are in view, so the means only count those. "}
+                    {"* Not a density per km of road, as road length is not known to the map: only a stand-in for it."}
+                </div>
+                <div class="btn-group" role="group">
+                    <button class="btn btn-sm btn-outline-secondary" onclick={save_summary}>{"Summary"}</button>
+                    {export_buttons}
+                </div>
+            </>
+        }
+    } else {
+        html!()
+    };
+
+    let button_class = |button_mode: Mode| {
+        classes!(
+            "btn",
+            "btn-sm",
+            if *mode == button_mode {
+                "btn-primary"
+            } else {
+                "btn-outline-primary"
+            }
+        )
+    };
+    html! {
+        <div class="card m-3" style="position: absolute; top: 0; left: 3rem; z-index: 1000; max-width: 24em;">
+            <div class="card-body py-2">
+                <div class="btn-group" role="group">
+                    <button class={button_class(Mode::Rectangle)} onclick={start(Mode::Rectangle)}>{"Rectangle"}</button>
+                    <button class={button_class(Mode::Polygon)} onclick={start(Mode::Polygon)}>{"Polygon"}</button>
+                    if *mode == Mode::Polygon {
+                        <button class="btn btn-sm btn-outline-success" disabled={vertices.len() < 3} onclick={finish}>{"Finish"}</button>
+                    }
+                    if !vertices.is_empty() {
+                        <button class="btn btn-sm btn-outline-secondary" onclick={clear}>{"Clear"}</button>
+                    }
+                </div>
+                {hint}
+                {results}
+            </div>
+        </div>
+    }
+}
@@ -1,11 +1,23 @@
+mod config;
+mod damage;
+mod damage_table;
+mod dashboard;
+mod export;
+mod filter_panel;
 mod leaflet;
+mod permalink;
 mod point_display;
 
-use yew::prelude::*;
+use std::rc::Rc;
 
-use crate::leaflet::MapComponent;
+use yew::prelude::*;
+use yew_hooks::{use_async_with_options, UseAsyncOptions};
+use yew_router::prelude::*;
 
-const SERVER_ADDR: &'static str = "http://localhost:8080";
+use crate::{
+    config::AppConfig,
+    permalink::{switch, Route},
+};
 
 fn main() {
     wasm_logger::init(wasm_logger::Config::default());
@@ -14,15 +26,57 @@ fn main() {
 
 #[function_component(App)]
 pub fn app() -> Html {
+    let config = use_async_with_options(
+        async { Ok::<_, ()>(Rc::new(config::load().await)) },
+        UseAsyncOptions::enable_auto(),
+    );
+
+    // The map is only created once it knows where to look
+    let content = match config.data {
+        Some(ref config) => html! {
+            <ContextProvider<Rc<AppConfig>> context={config.clone()}>
+                <Switch<Route> render={switch} />
+            </ContextProvider<Rc<AppConfig>>>
+        },
+        None => html! {
+            <div class="d-flex justify-content-center my-5">
+                <div class="spinner-border" role="status"></div>
+            </div>
+        },
+    };
+
     html! {
-        <main style="height: 100%" class="">
-            <nav class="navbar bg-body-tertiary">
-                <div class="container-fluid">
-                    <span class="navbar-brand mb-0 h1">{"Navbar"}</span>
-                </div>
-            </nav>
+        <BrowserRouter>
+            <main style="height: 100%" class="">
+                <Navbar />
 
-            <MapComponent style="height: 100%"/>
-        </main>
+                {content}
+            </main>
+        </BrowserRouter>
+    }
+}
+
+/// Name of the app and links to its pages
+#[function_component]
+fn Navbar() -> Html {
+    let route = use_route::<Route>();
+    let link = |to: Route, title: &'static str| {
+        let classes = classes!(
+            "nav-link",
+            (route.as_ref() == Some(&to)).then_some("active")
+        );
+        html!(<Link<Route> {classes} {to}>{title}</Link<Route>>)
+    };
+
+    html! {
+        <nav class="navbar navbar-expand bg-body-tertiary">
+            <div class="container-fluid">
+                <span class="navbar-brand mb-0 h1">{"Pothole detection"}</span>
+                <div class="navbar-nav me-auto">
+                    {link(Route::Map, "Map")}
+                    {link(Route::Dashboard, "Dashboard")}
+                </div>
+            </div>
+        </nav>
     }
 }
@@ -0,0 +1,96 @@
+//! Shareable links: the map's view, style and selected point are kept in the URL's query,
+//! like `/?lat=55.75&lon=37.62&zoom=16&layer=_&point=42&hide=repair,other`.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::{dashboard::Dashboard, leaflet::MapComponent};
+
+#[derive(Clone, Routable, PartialEq)]
+pub enum Route {
+    #[at("/")]
+    Map,
+    #[at("/dashboard")]
+    Dashboard,
+    #[not_found]
+    #[at("/404")]
+    NotFound,
+}
+
+pub fn switch(route: Route) -> Html {
+    match route {
+        Route::Map => html!(<MapComponent style="height: 100%"/>),
+        Route::Dashboard => html!(<Dashboard />),
+        Route::NotFound => html!(<Redirect<Route> to={Route::Map}/>),
+    }
+}
+
+/// Query of the map route. Missing values are taken from the config.
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Permalink {
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub zoom: Option<f64>,
+    /// Style name in the tile cache
+    pub layer: Option<String>,
+    /// Id of the selected damage
+    pub point: Option<u64>,
+    /// Damage classes not drawn, separated by commas
+    pub hide: Option<String>,
+}
+
+impl Permalink {
+    /// Coordinates are rounded to about a meter, to keep links short
+    pub fn new(
+        view: MapView,
+        layer: Option<String>,
+        point: Option<u64>,
+        hidden: &BTreeSet<String>,
+    ) -> Self {
+        let round = |value: f64| (value * 1e5).round() / 1e5;
+        Permalink {
+            lat: Some(round(view.lat)),
+            lon: Some(round(view.lon)),
+            zoom: Some(view.zoom),
+            layer,
+            point,
+            hide: (!hidden.is_empty()).then(|| {
+                hidden
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+        }
+    }
+
+    /// Keys of the damage classes not drawn
+    pub fn hidden_classes(&self) -> BTreeSet<String> {
+        self.hide
+            .iter()
+            .flat_map(|hide| hide.split(','))
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The view from the link, if it has one
+    pub fn view(&self) -> Option<MapView> {
+        Some(MapView {
+            lat: self.lat?,
+            lon: self.lon?,
+            zoom: self.zoom?,
+        })
+    }
+}
+
+/// Center and zoom of the map
+#[derive(Clone, Copy, PartialEq)]
+pub struct MapView {
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: f64,
+}
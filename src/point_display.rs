@@ -1,10 +1,10 @@
 use std::rc::Rc;
 
-use common_data::{DamageType, RoadDamage};
+use common_data::RoadDamage;
 use yew::{prelude::*, suspense::use_future_with_deps};
 use yew_hooks::use_previous;
 
-use crate::{leaflet::icons::ShowIcon, SERVER_ADDR};
+use crate::{config::AppConfig, damage::damage_label, leaflet::icons::ShowIcon};
 
 #[derive(Properties)]
 pub struct PointDisplayProps {
@@ -98,11 +98,14 @@ pub fn SamplePointInfo(props: &SampleInfo) -> Html {
 
 #[function_component]
 pub fn DetailedPointInfo(props: &SampleInfo) -> HtmlResult {
+    let config = use_context::<Rc<AppConfig>>().expect("App provides the config");
+    let backend_url = config.backend_url.clone();
     let info = props.sample_info.clone();
     let response = use_future_with_deps(
         {
-            |info: Rc<RoadDamage>| async move {
-                let response = frontend_requests::get_info_by_id(SERVER_ADDR, info.id).await;
+            let backend_url = backend_url.clone();
+            move |info: Rc<RoadDamage>| async move {
+                let response = frontend_requests::get_info_by_id(&backend_url, info.id).await;
                 prokio::time::sleep(std::time::Duration::from_secs_f32(0.2f32)).await;
                 response
             }
@@ -112,24 +115,12 @@ pub fn DetailedPointInfo(props: &SampleInfo) -> HtmlResult {
     let info = props.sample_info.clone();
     match *response {
         Ok(ref more_info) => {
-            let kind = match info.damage_type {
-                DamageType::Alligator_crack => "Alligator crack",
-                DamageType::Rutting_bump_pothole_separation => "Rutting/bump/pothole/separation",
-                DamageType::Linear_longitudinal_crack => "Linear longitudinal crack",
-                DamageType::White_line_blur => "White line blur",
-                DamageType::Linear_lateral_crack => "Linear lateral crack",
-                DamageType::Cross_walk_blur => "Cross walk blur",
-                DamageType::Utility_hole_maintenance_hatch => "Utility hole/maintenance hatch",
-                DamageType::Repair => "Repair",
-                _ => "Unknown",
-            };
+            let kind = damage_label(&info.damage_type);
 
             let kind_more = &more_info.top_type;
 
             let score = more_info.top_certainty;
 
-            use crate::SERVER_ADDR;
-
             Ok(html!(
                 <>
                     <h1>{"Point "}{info.id}<ShowIcon damage_type={info.damage_type} /></h1>
@@ -139,7 +130,7 @@ pub fn DetailedPointInfo(props: &SampleInfo) -> HtmlResult {
                     <p>{"Latitude: "}{info.latitude}</p>
                     <div class="card">
                         <div class="card-body">
-                            <img style="width: 100%" src={format!("{SERVER_ADDR}/image/of-point/{}", info.id)} />
+                            <img style="width: 100%" src={format!("{backend_url}/image/of-point/{}", info.id)} />
                         </div>
                     </div>
                     <hr />